@@ -1,4 +1,6 @@
 pub mod http_proxy;
 pub mod listen;
+pub mod options;
 pub mod pcp_proxy;
+pub mod replay;
 pub mod utils;
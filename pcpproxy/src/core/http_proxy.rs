@@ -1 +1,2 @@
 pub mod proxy_http;
+pub mod tip;
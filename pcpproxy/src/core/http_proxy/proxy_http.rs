@@ -1,14 +1,16 @@
 use std::{
     net::{IpAddr, SocketAddr},
     num::NonZeroU16,
-    str::FromStr,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use futures::Future;
-use regex::Regex;
+use peercastoxide_lib::{
+    net::canonical,
+    pcp::http::{read_request_head, ChannelRequest},
+};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    io::{AsyncBufReadExt, BufReader},
     net::{
         tcp::{OwnedReadHalf, OwnedWriteHalf},
         TcpStream,
@@ -18,10 +20,17 @@ use tokio::{
 
 use crate::{
     core::{
+        http_proxy::tip::Tip,
+        options::ProxyOptions,
         pcp_proxy::pipe::pipe_pcp,
-        utils::{disconnect_conn_of_download, disconnect_conn_of_upload, pipe_raw, PipeError},
+        utils::{
+            disconnect_conn_of_download, disconnect_conn_of_upload, pipe_raw, port_of, write_out,
+            PipeError,
+        },
+    },
+    features::{
+        fault::ConnectionType, output::ndjson::NDJson, real_server_listener::listen_for::listen_for,
     },
-    features::{output::ndjson::NDJson, real_server_listener::listen_for::listen_for},
 };
 
 pub async fn pipe_request_header<T>(
@@ -46,10 +55,7 @@ where
         }
         all += &line;
         line = on_line(line).await;
-        outgoing
-            .write_all(line.as_bytes())
-            .await
-            .map_err(|err| PipeError::ByOutgoing(anyhow::Error::new(err)))?;
+        write_out(outgoing, line.as_bytes(), None, output).await?;
         if line.trim_end().is_empty() {
             break;
         }
@@ -77,10 +83,7 @@ where
         if result == 0 {
             return Ok(false);
         }
-        outgoing
-            .write_all(line.as_bytes())
-            .await
-            .map_err(|err| PipeError::ByOutgoing(anyhow::Error::new(err)))?;
+        write_out(outgoing, line.as_bytes(), None, output).await?;
         all += &line;
         line = on_line(line).await;
         if line.trim_end().is_empty() {
@@ -98,31 +101,38 @@ async fn pipe_http_request(
     ip_addr_from_real_server: IpAddr,
     port: NonZeroU16,
     output: &NDJson,
+    options: &ProxyOptions,
 ) -> Result<bool, PipeError> {
     loop {
         let replacement_pair = std::sync::Mutex::new(None);
-        let pcp = std::sync::Mutex::new(false);
+        let head = std::sync::Mutex::new(String::new());
         let remain = pipe_request_header(
             incoming,
             outgoing,
             |mut line| async {
-                let pattern = r"^GET /(?:pls|stream)/(?:[0-9A-Fa-f]+)\?tip=([^&]+).* HTTP/.+\r?\n$";
-                if let Some(capture) = Regex::new(pattern).unwrap().captures(&line) {
-                    let tip_host = capture[1].to_owned();
-                    let port = listen_for(
-                        real_server_port,
-                        ip_addr_from_real_server,
-                        port,
-                        SocketAddr::from_str(&tip_host).unwrap(),
-                    )
+                head.lock().unwrap().push_str(&line);
+                if let Some(tip) = Tip::find(&line) {
+                    let result = async {
+                        let tip_addr = tip.resolve().await?;
+                        listen_for(
+                            real_server_port,
+                            ip_addr_from_real_server,
+                            port,
+                            tip_addr,
+                            options.clone(),
+                        )
+                        .await
+                    }
                     .await;
-                    let replace_with =
-                        SocketAddr::new(ip_addr_from_real_server, port.get()).to_string();
-                    line = line.replace(&tip_host, &replace_with);
-                    *replacement_pair.lock().unwrap() = Some((tip_host, replace_with));
-                }
-                if line.starts_with("x-peercast-pcp:") {
-                    *pcp.lock().unwrap() = true;
+                    match result {
+                        Ok(port) => {
+                            let replace_with =
+                                SocketAddr::new(ip_addr_from_real_server, port.get());
+                            line = tip.replace(&line, replace_with);
+                            *replacement_pair.lock().unwrap() = Some((tip.value, replace_with));
+                        }
+                        Err(err) => output.error("tip", &err),
+                    }
                 }
                 line
             },
@@ -132,19 +142,25 @@ async fn pipe_http_request(
         if let Some((from, to)) = replacement_pair.lock().unwrap().as_ref() {
             output.info(&format!("Proxy: Replaced {} with {}", from, to));
         }
-        if *pcp.lock().unwrap() {
-            return Ok(true);
-        }
         if !remain {
             return Ok(false);
         }
+        let head = head.into_inner().unwrap();
+        let req = read_request_head(&mut head.as_bytes())
+            .await
+            .and_then(|head| ChannelRequest::from_head(&head));
+        match req {
+            Ok(Some(req)) if req.is_relay() => return Ok(true),
+            Ok(_) => {}
+            Err(err) => output.error("http", &err),
+        }
     }
 }
 
 fn is_pcp(line: &str) -> bool {
-    let mut header = line.split(':');
-    header.next().unwrap().trim() == "Content-Type"
-        && header.next().unwrap().trim() == "application/x-peercast-pcp"
+    line.split_once(':').is_some_and(|(name, value)| {
+        name.trim() == "Content-Type" && value.trim() == "application/x-peercast-pcp"
+    })
 }
 
 async fn pipe_http_response(
@@ -180,24 +196,36 @@ pub async fn proxy_http(
     real_server_host: &str,
     ip_addr_from_real_server: IpAddr,
     listen_port: NonZeroU16,
+    options: &ProxyOptions,
 ) -> Result<()> {
-    let client_host = format!("{}", client.peer_addr().unwrap());
+    let real_server_port = port_of(real_server_host)?;
+    let client_addr = canonical(client.peer_addr()?);
+    let client_host = client_addr.to_string();
     let (client_incoming, mut client_outgoing) = client.into_split();
-    let server = TcpStream::connect(real_server_host).await?;
+    let server = TcpStream::connect(real_server_host)
+        .await
+        .with_context(|| format!("connecting to {}", real_server_host))?;
+    let capture = options
+        .recorder
+        .connection(client_addr, server.peer_addr()?);
     let (server_incoming, mut server_outgoing) = server.into_split();
-    let server_host_string = real_server_host.into();
-    let client_host_clone = client_host.clone();
-    let real_server_port = real_server_host.split(':').nth(1).unwrap().parse().unwrap();
+    let (upload_output, download_output) = options.outputs(
+        client_host,
+        real_server_host.into(),
+        capture,
+        ConnectionType::Http,
+    );
+    let options_clone = options.clone();
     spawn(async move {
         let mut client_incoming = BufReader::new(client_incoming);
-        let output = NDJson::upload(client_host_clone, server_host_string);
         let result = pipe_http_request(
             &mut client_incoming,
             &mut server_outgoing,
             real_server_port,
             ip_addr_from_real_server,
             listen_port,
-            &output,
+            &upload_output,
+            &options_clone,
         )
         .await;
         let result = if let Ok(true) = result {
@@ -207,19 +235,20 @@ pub async fn proxy_http(
                 real_server_port,
                 ip_addr_from_real_server,
                 listen_port,
-                &output,
+                &upload_output,
+                &options_clone,
             )
             .await
         } else {
             result.map(|_| ())
         };
-        disconnect_conn_of_upload(result, output).unwrap();
+        disconnect_conn_of_upload(result, upload_output);
     });
-    let server_host_string = real_server_host.into();
+    let options = options.clone();
     spawn(async move {
         let mut server_incoming = BufReader::new(server_incoming);
-        let output = NDJson::download(client_host, server_host_string);
-        let result = pipe_http_response(&mut server_incoming, &mut client_outgoing, &output).await;
+        let result =
+            pipe_http_response(&mut server_incoming, &mut client_outgoing, &download_output).await;
         let result = if let Ok(true) = result {
             pipe_pcp(
                 server_incoming,
@@ -227,15 +256,79 @@ pub async fn proxy_http(
                 real_server_port,
                 ip_addr_from_real_server,
                 listen_port,
-                &output,
+                &download_output,
+                &options,
             )
             .await
         } else if let Ok(false) = result {
-            pipe_raw(server_incoming, client_outgoing, &output).await
+            pipe_raw(server_incoming, client_outgoing, &download_output).await
         } else {
             result.map(|_| ())
         };
-        disconnect_conn_of_download(result, output).unwrap();
+        disconnect_conn_of_download(result, download_output);
     });
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU16;
+
+    use tokio::{
+        io::{AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::{core::options::ProxyOptions, features::fault::ConnectionType};
+
+    use super::pipe_http_request;
+
+    #[tokio::test]
+    async fn test_pipe_http_request_of_relay() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (proxy, _) = listener.accept().await.unwrap();
+        let (incoming, mut outgoing) = proxy.into_split();
+        let mut incoming = BufReader::new(incoming);
+        let options = ProxyOptions::default();
+        let (output, _) =
+            options.outputs("client".into(), "server".into(), None, ConnectionType::Http);
+        let port = NonZeroU16::new(7144).unwrap();
+        let ip = "127.0.0.1".parse().unwrap();
+
+        client
+            .write_all(b"GET /index.html HTTP/1.1\r\nX-Peercast-Pcp: 1\r\n\r\n")
+            .await
+            .unwrap();
+        client
+            .write_all(b"GET /channel/0123456789abcdef0123456789abcdef HTTP/1.0\r\nX-Peercast-Pcp: 1\r\n\r\n")
+            .await
+            .unwrap();
+        let result = pipe_http_request(
+            &mut incoming,
+            &mut outgoing,
+            port,
+            ip,
+            port,
+            &output,
+            &options,
+        )
+        .await;
+        assert!(matches!(result, Ok(true)));
+
+        client.shutdown().await.unwrap();
+        let result = pipe_http_request(
+            &mut incoming,
+            &mut outgoing,
+            port,
+            ip,
+            port,
+            &output,
+            &options,
+        )
+        .await;
+        assert!(matches!(result, Ok(false)));
+    }
+}
@@ -0,0 +1,110 @@
+use std::{net::SocketAddr, ops::Range};
+
+use anyhow::{anyhow, Context, Result};
+use peercastoxide_lib::pcp::http::{encode_tip, ChannelRequest};
+use tokio::net::lookup_host;
+
+/// The `tip` parameter of a request line for a channel.
+#[derive(Debug, PartialEq)]
+pub struct Tip {
+    /// The percent-decoded value, e.g. `[::1]:7144` or `example.com:7144`
+    pub value: String,
+    /// Where the encoded value is in the line
+    range: Range<usize>,
+}
+
+impl Tip {
+    pub fn find(line: &str) -> Option<Self> {
+        let target = line.strip_prefix("GET ")?.split(' ').next()?;
+        let value = ChannelRequest::from_target(target)?.tip?;
+        // the other parameters are kept as they are, so only the value is replaced
+        let mut start = "GET ".len() + target.find('?')? + 1;
+        for param in target.split_once('?')?.1.split('&') {
+            if let Some(encoded) = param.strip_prefix("tip=") {
+                start += "tip=".len();
+                return Some(Self {
+                    value,
+                    range: start..start + encoded.len(),
+                });
+            }
+            start += param.len() + 1;
+        }
+        None
+    }
+
+    /// The address of the tip, looking up its host name if it isn't an IP address.
+    pub async fn resolve(&self) -> Result<SocketAddr> {
+        if let Ok(addr) = self.value.parse() {
+            return Ok(addr);
+        }
+        lookup_host(&self.value)
+            .await
+            .with_context(|| format!("resolving tip {}", self.value))?
+            .next()
+            .ok_or_else(|| anyhow!("no address for tip {}", self.value))
+    }
+
+    /// `line` with the tip replaced with `addr`.
+    pub fn replace(&self, line: &str, addr: SocketAddr) -> String {
+        let encoded = encode_tip(&addr.to_string());
+        let mut line = line.to_owned();
+        line.replace_range(self.range.clone(), &encoded);
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tip;
+
+    #[test]
+    fn test_tip() {
+        let line = "GET /stream/0123456789abcdef0123456789abcdef?tip=192.168.0.1:7144 HTTP/1.0\r\n";
+        let tip = Tip::find(line).unwrap();
+        assert_eq!(tip.value, "192.168.0.1:7144");
+        assert_eq!(
+            tip.replace(line, "127.0.0.1:50000".parse().unwrap()),
+            "GET /stream/0123456789abcdef0123456789abcdef?tip=127.0.0.1:50000 HTTP/1.0\r\n"
+        );
+
+        let line = "GET /pls/0123456789abcdef0123456789abcdef?v=1&tip=%5B%3A%3A1%5D%3A7144&x=tip HTTP/1.1\r\n";
+        let tip = Tip::find(line).unwrap();
+        assert_eq!(tip.value, "[::1]:7144");
+        assert_eq!(
+            tip.replace(line, "[fe80::1]:50000".parse().unwrap()),
+            "GET /pls/0123456789abcdef0123456789abcdef?v=1&tip=%5Bfe80::1%5D:50000&x=tip HTTP/1.1\r\n"
+        );
+
+        let line =
+            "GET /stream/0123456789abcdef0123456789abcdef?tip=peercast.example:7144 HTTP/1.0\n";
+        assert_eq!(Tip::find(line).unwrap().value, "peercast.example:7144");
+        assert!(
+            Tip::find("GET /stream/0123456789abcdef0123456789abcdef?tipx=1 HTTP/1.0\r\n").is_none()
+        );
+        assert!(Tip::find("GET /index.html?tip=1.2.3.4:7144 HTTP/1.0\r\n").is_none());
+        assert!(Tip::find("GET /stream/0123abcd?tip=1.2.3.4:7144 HTTP/1.0\r\n").is_none());
+    }
+
+    #[test]
+    fn test_tip_of_each_form() {
+        let addr = "[::1]:50000".parse().unwrap();
+        for (line, replaced) in [
+            (
+                "GET /stream/0123456789abcdef0123456789abcdef.flv?tip=1.2.3.4:7144 HTTP/1.0\r\n",
+                "GET /stream/0123456789abcdef0123456789abcdef.flv?tip=%5B::1%5D:50000 HTTP/1.0\r\n",
+            ),
+            (
+                "GET /pls/0123456789abcdef0123456789abcdef.m3u?tip=1.2.3.4:7144 HTTP/1.1\r\n",
+                "GET /pls/0123456789abcdef0123456789abcdef.m3u?tip=%5B::1%5D:50000 HTTP/1.1\r\n",
+            ),
+            (
+                "GET /channel/0123456789abcdef0123456789abcdef?tip=1.2.3.4:7144 HTTP/1.0\r\n",
+                "GET /channel/0123456789abcdef0123456789abcdef?tip=%5B::1%5D:50000 HTTP/1.0\r\n",
+            ),
+        ] {
+            let tip = Tip::find(line).unwrap();
+            assert_eq!(tip.value, "1.2.3.4:7144");
+            assert_eq!(tip.replace(line, addr), replaced);
+        }
+    }
+}
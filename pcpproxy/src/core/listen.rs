@@ -1,84 +1,105 @@
 use std::net::IpAddr;
-use std::net::Ipv4Addr;
-use std::net::Ipv6Addr;
-use std::net::SocketAddr;
 use std::num::NonZeroU16;
+use std::time::Duration;
 
-use anyhow::Result;
-use tokio::net::TcpListener;
+use anyhow::{Context, Result};
+use peercastoxide_lib::net::{bind_dual_stack, canonical};
 use tokio::net::TcpStream;
 use tokio::spawn;
+use tokio::time::sleep;
 
 use crate::core::pcp_proxy::header::check_header;
 use crate::core::pcp_proxy::header::Header;
-use crate::features::output::ndjson::NDJson;
+use crate::features::fault::ConnectionType;
+use crate::features::output::ndjson::output_error;
 
 use super::http_proxy::proxy_http::proxy_http;
+use super::options::ProxyOptions;
 use super::pcp_proxy::pipe::pipe_pcp;
 use super::utils::disconnect_conn_of_download;
 use super::utils::disconnect_conn_of_upload;
 use super::utils::pipe_raw;
+use super::utils::port_of;
 
 async fn proxy_pcp(
     client: TcpStream,
     server_host: &str,
     ip_addr_from_real_server: IpAddr,
     listen_port: NonZeroU16,
+    options: &ProxyOptions,
 ) -> Result<()> {
-    let client_host = format!("{}", client.peer_addr().unwrap());
+    let real_server_port = port_of(server_host)?;
+    let client_addr = canonical(client.peer_addr()?);
+    let client_host = client_addr.to_string();
     let (client_incoming, client_outgoing) = client.into_split();
-    let server = TcpStream::connect(server_host).await?;
+    let server = TcpStream::connect(server_host)
+        .await
+        .with_context(|| format!("connecting to {}", server_host))?;
+    let capture = options
+        .recorder
+        .connection(client_addr, server.peer_addr()?);
     let (server_incoming, server_outgoing) = server.into_split();
-    let server_host_string = server_host.into();
-    let client_host_clone = client_host.clone();
-    let real_server_port = server_host.split(':').nth(1).unwrap().parse().unwrap();
+    let (upload_output, download_output) = options.outputs(
+        client_host,
+        server_host.into(),
+        capture,
+        ConnectionType::Pcp,
+    );
+    let options_clone = options.clone();
     spawn(async move {
-        let output = NDJson::upload(client_host_clone, server_host_string);
         let result = pipe_pcp(
             client_incoming,
             server_outgoing,
             real_server_port,
             ip_addr_from_real_server,
             listen_port,
-            &output,
+            &upload_output,
+            &options_clone,
         )
         .await;
-        disconnect_conn_of_upload(result, output).unwrap();
+        disconnect_conn_of_upload(result, upload_output);
     });
-    let server_host_string = server_host.into();
+    let options = options.clone();
     spawn(async move {
-        let output = NDJson::download(client_host, server_host_string);
         let result = pipe_pcp(
             server_incoming,
             client_outgoing,
             real_server_port,
             ip_addr_from_real_server,
             listen_port,
-            &output,
+            &download_output,
+            &options,
         )
         .await;
-        disconnect_conn_of_download(result, output).unwrap()
+        disconnect_conn_of_download(result, download_output)
     });
     Ok(())
 }
 
-async fn proxy_raw(client: TcpStream, server_host: &str) -> Result<()> {
-    let client_host = format!("{}", client.peer_addr().unwrap());
+async fn proxy_raw(client: TcpStream, server_host: &str, options: &ProxyOptions) -> Result<()> {
+    let client_addr = canonical(client.peer_addr()?);
+    let client_host = client_addr.to_string();
     let (client_incoming, client_outgoing) = client.into_split();
-    let server = TcpStream::connect(server_host).await?;
+    let server = TcpStream::connect(server_host)
+        .await
+        .with_context(|| format!("connecting to {}", server_host))?;
+    let capture = options
+        .recorder
+        .connection(client_addr, server.peer_addr()?);
     let (server_incoming, server_outgoing) = server.into_split();
-    let server_host_string = server_host.into();
-    let client_host_clone = client_host.clone();
+    let (upload_output, download_output) = options.outputs(
+        client_host,
+        server_host.into(),
+        capture,
+        ConnectionType::Raw,
+    );
     spawn(async move {
-        let output = NDJson::upload(client_host_clone, server_host_string);
-        let result = pipe_raw(client_incoming, server_outgoing, &output).await;
-        disconnect_conn_of_upload(result, output).unwrap();
+        let result = pipe_raw(client_incoming, server_outgoing, &upload_output).await;
+        disconnect_conn_of_upload(result, upload_output);
     });
-    let server_host_string = server_host.into();
     spawn(async move {
-        let output = NDJson::download(client_host, server_host_string);
-        let result = pipe_raw(server_incoming, client_outgoing, &output).await;
-        disconnect_conn_of_download(result, output).unwrap()
+        let result = pipe_raw(server_incoming, client_outgoing, &download_output).await;
+        disconnect_conn_of_download(result, download_output)
     });
     Ok(())
 }
@@ -88,18 +109,33 @@ async fn on_connect(
     ip_addr_from_real_server: IpAddr,
     port: NonZeroU16,
     real_server_host: &str,
+    options: &ProxyOptions,
 ) -> Result<()> {
     let (mut client_incoming, _) = client.split();
     let header = check_header(&mut client_incoming).await?;
     match header {
         Header::Http => {
-            proxy_http(client, real_server_host, ip_addr_from_real_server, port).await?;
+            proxy_http(
+                client,
+                real_server_host,
+                ip_addr_from_real_server,
+                port,
+                options,
+            )
+            .await?;
         }
         Header::Pcp => {
-            proxy_pcp(client, real_server_host, ip_addr_from_real_server, port).await?;
+            proxy_pcp(
+                client,
+                real_server_host,
+                ip_addr_from_real_server,
+                port,
+                options,
+            )
+            .await?;
         }
         Header::Unknown => {
-            proxy_raw(client, real_server_host).await?;
+            proxy_raw(client, real_server_host, options).await?;
         }
         Header::Empty => {}
     };
@@ -110,27 +146,35 @@ pub async fn listen(
     listen_port: NonZeroU16,
     ip_addr_from_real_server: IpAddr,
     real_server_host: &str,
-) {
-    let unspecified_addr: IpAddr = match ip_addr_from_real_server {
-        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
-        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
-    };
-
-    let server = TcpListener::bind(SocketAddr::new(unspecified_addr, listen_port.get()))
-        .await
-        .unwrap();
+    options: ProxyOptions,
+) -> Result<()> {
+    let server = bind_dual_stack(listen_port.get())
+        .with_context(|| format!("listening on port {}", listen_port))?;
     loop {
-        let (incoming_socket, _) = server.accept().await.unwrap();
+        let (incoming_socket, client_addr) = match server.accept().await {
+            Ok(ok) => ok,
+            Err(err) => {
+                output_error(None, Some(real_server_host), "accept", &err.into());
+                // Waits for the cause such as running out of file descriptors to go away.
+                sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
         let real_server_host = real_server_host.to_owned();
+        let options = options.clone();
         spawn(async move {
-            on_connect(
+            let result = on_connect(
                 incoming_socket,
                 ip_addr_from_real_server,
                 listen_port,
                 &real_server_host,
+                &options,
             )
-            .await
-            .unwrap();
+            .await;
+            if let Err(err) = result {
+                let client_host = canonical(client_addr).to_string();
+                output_error(Some(&client_host), Some(&real_server_host), "connect", &err);
+            }
         });
     }
 }
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use crate::features::{
+    capture::{ConnectionCapture, Recorder},
+    fault::{ConnectionType, FaultConfig},
+    output::{filter::OutputFilter, ndjson::NDJson},
+    rewrite::RewriteRules,
+    script::AtomScript,
+};
+
+/// What the proxy does to connections besides piping them, shared by all connections.
+#[derive(Clone, Default)]
+pub struct ProxyOptions {
+    pub recorder: Recorder,
+    pub filter: Arc<OutputFilter>,
+    pub rules: Arc<RewriteRules>,
+    pub faults: Arc<FaultConfig>,
+    pub script: Option<Arc<AtomScript>>,
+}
+
+impl ProxyOptions {
+    /// The outputs for upload and download of a connection.
+    pub fn outputs(
+        &self,
+        client_host: String,
+        server_host: String,
+        capture: Option<ConnectionCapture>,
+        connection: ConnectionType,
+    ) -> (NDJson, NDJson) {
+        let (upload, download) =
+            NDJson::connect(client_host, server_host, capture, self.filter.clone());
+        let script = self.script.as_ref().map(|x| x.connection());
+        (
+            upload
+                .with_faults(&self.faults, connection)
+                .with_script(script.clone()),
+            download
+                .with_faults(&self.faults, connection)
+                .with_script(script),
+        )
+    }
+}
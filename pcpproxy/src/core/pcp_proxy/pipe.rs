@@ -1,20 +1,17 @@
-use std::borrow::Cow;
 use std::net::{IpAddr, SocketAddr};
 use std::num::NonZeroU16;
 
 use anyhow::Result;
+use peercastoxide_lib::pcp::atom::{
+    well_known_identifiers::{BCST, HELO, HOST, IP, PORT},
+    AtomStreamReader, AtomStreamWriter, UnknownAtom,
+};
 use tokio::io::AsyncRead;
 use tokio::net::tcp::OwnedWriteHalf;
 
-use crate::core::utils::PipeError;
+use crate::core::options::ProxyOptions;
+use crate::core::utils::{write_out, PipeError};
 use crate::features::output::ndjson::NDJson;
-use crate::features::pcp::atom::AtomChild;
-use crate::features::pcp::atom_identifier::{BCST, HELO};
-use crate::features::pcp::{
-    atom::Atom,
-    atom_identifier::{HOST, IP, PORT},
-    atom_stream::{AtomStreamReader, AtomStreamWriter},
-};
 use crate::features::real_server_listener::listen_for::listen_for;
 
 pub fn big_vec<T: Default>(len: usize) -> Vec<T> {
@@ -27,123 +24,109 @@ pub fn big_vec<T: Default>(len: usize) -> Vec<T> {
     buf
 }
 
-fn find_ip_port_pair_indices<'a>(
-    children: &'a [Atom],
-) -> Vec<((usize, IpAddr), (usize, NonZeroU16))> {
-    let filter_map = |identifier: &[u8; 4], (i, x): (usize, &'a Atom)| {
-        if x.identifier() != identifier {
-            return None;
+fn is_port_of(atom: &UnknownAtom, port: NonZeroU16) -> bool {
+    match atom {
+        UnknownAtom::Child(child) if child.identifier().0.as_ref() == PORT => {
+            child.to_u16().ok() == Some(port.get())
         }
-        let child = if let Atom::Child(child) = &x {
-            child
-        } else {
-            return None;
-        };
-        Some((i, child))
+        _ => false,
+    }
+}
+
+fn find_ip_port_pair_indices(
+    children: &[UnknownAtom],
+) -> Vec<((usize, IpAddr), (usize, NonZeroU16))> {
+    let children_of = |identifier: &'static [u8; 4]| {
+        children
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, x)| match x {
+                UnknownAtom::Child(child) if child.identifier().0.as_ref() == identifier => {
+                    Some((i, child))
+                }
+                _ => None,
+            })
     };
-    let ip_indices = children
-        .iter()
-        .enumerate()
-        .filter_map(|item| filter_map(IP, item))
-        .map(|(idx, atom)| (idx, atom.to_ip()));
-    let port_indices = children
-        .iter()
-        .enumerate()
-        .filter_map(|item| filter_map(PORT, item))
-        .map(|(idx, atom)| (idx, atom.to_u16().try_into().unwrap()));
-    ip_indices.zip(port_indices).collect()
+    let ip_indices = children_of(IP).map(|(idx, atom)| (idx, atom.to_ip().ok()));
+    let port_indices = children_of(PORT).map(|(idx, atom)| {
+        let port = atom.to_u16().ok().and_then(NonZeroU16::new);
+        (idx, port)
+    });
+    ip_indices
+        .zip(port_indices)
+        .filter_map(|((ip_idx, ip), (port_idx, port))| Some(((ip_idx, ip?), (port_idx, port?))))
+        .collect()
 }
 
 fn replace_ip_port_pair(
-    children: &mut [Atom],
+    children: &mut [UnknownAtom],
     ip_idx: usize,
     port_idx: usize,
     ip: IpAddr,
     port: NonZeroU16,
 ) {
-    if let Atom::Child(ip_atom) = &mut children[ip_idx] {
-        *ip_atom = AtomChild::ip(Cow::Borrowed(IP), ip);
-    }
-    if let Atom::Child(port_atom) = &mut children[port_idx] {
-        *port_atom = AtomChild::u16(Cow::Borrowed(PORT), port.into());
-    }
+    children[ip_idx] = match ip {
+        IpAddr::V4(ip) => UnknownAtom::ipv4(IP, &ip),
+        IpAddr::V6(ip) => UnknownAtom::ipv6(IP, &ip),
+    };
+    children[port_idx] = UnknownAtom::u16(PORT, port.get());
 }
 
 pub async fn pipe_pcp(
     incoming: impl AsyncRead + Unpin + Send + Sync,
-    outgoing: OwnedWriteHalf,
+    mut outgoing: OwnedWriteHalf,
     real_server_port: NonZeroU16,
     ip_addr_from_real_server: IpAddr,
     listen_port: NonZeroU16,
     output: &NDJson,
+    options: &ProxyOptions,
 ) -> Result<(), PipeError> {
     let mut atom_stream_reader = AtomStreamReader::new(incoming);
-    let mut atom_stream_writer = AtomStreamWriter::new(outgoing);
     loop {
-        let mut atom = if let Some(some) = atom_stream_reader
-            .read()
+        let mut atom = atom_stream_reader
+            .read_unknown_atom()
             .await
-            .map_err(PipeError::ByIncoming)?
-        {
-            some
-        } else {
-            return Ok(());
-        };
+            .map_err(PipeError::ByIncoming)?;
         output.output(&atom);
 
         match &mut atom {
-            Atom::Parent(parent) if parent.identifier() == BCST => {
+            UnknownAtom::Parent(parent) if parent.identifier().0.as_ref() == BCST => {
                 for child in parent
                     .children_mut()
                     .iter_mut()
-                    .filter(|x| x.identifier() == HOST)
-                    .filter_map(|x| {
-                        if let Atom::Parent(parent) = x {
+                    .filter_map(|x| match x {
+                        UnknownAtom::Parent(parent) if parent.identifier().0.as_ref() == HOST => {
                             Some(parent)
-                        } else {
-                            None
                         }
+                        _ => None,
                     })
                     .flat_map(|x| x.children_mut())
-                    .filter(|x| x.identifier() == PORT)
-                    .filter_map(|x| {
-                        if let Atom::Child(child) = x {
-                            Some(child)
-                        } else {
-                            None
-                        }
-                    })
-                    .filter(|x| x.to_u16() == real_server_port.get())
+                    .filter(|x| is_port_of(x, real_server_port))
                 {
-                    *child = AtomChild::u16(Cow::Borrowed(PORT), listen_port.get());
+                    *child = UnknownAtom::u16(PORT, listen_port.get());
                     output.info(&format!(
                         "Proxy: Replaced {} with {}",
                         real_server_port, listen_port
                     ));
                 }
             }
-            Atom::Parent(parent) if parent.identifier() == HELO => {
-                if parent.children().iter().all(|x| x.identifier() != PORT) {
-                    parent.children_mut().push(Atom::Child(AtomChild::u16(
-                        Cow::Borrowed(PORT),
-                        listen_port.get(),
-                    )));
+            UnknownAtom::Parent(parent) if parent.identifier().0.as_ref() == HELO => {
+                if parent
+                    .children()
+                    .iter()
+                    .all(|x| x.identifier().0.as_ref() != PORT)
+                {
+                    parent
+                        .children_mut()
+                        .push(UnknownAtom::u16(PORT, listen_port.get()));
                     output.info(&format!("Proxy: Append AtomChild(port, {})", listen_port));
                 } else {
                     for child in parent
                         .children_mut()
                         .iter_mut()
-                        .filter(|x| x.identifier() == PORT)
-                        .filter_map(|x| {
-                            if let Atom::Child(child) = x {
-                                Some(child)
-                            } else {
-                                None
-                            }
-                        })
-                        .filter(|x| x.to_u16() == real_server_port.get())
+                        .filter(|x| is_port_of(x, real_server_port))
                     {
-                        *child = AtomChild::u16(Cow::Borrowed(PORT), listen_port.get());
+                        *child = UnknownAtom::u16(PORT, listen_port.get());
                         output.info(&format!(
                             "Proxy: Replaced {} with {}",
                             real_server_port, listen_port
@@ -151,17 +134,25 @@ pub async fn pipe_pcp(
                     }
                 }
             }
-            Atom::Parent(parent) if parent.identifier() == HOST => {
+            UnknownAtom::Parent(parent) if parent.identifier().0.as_ref() == HOST => {
                 let indices = find_ip_port_pair_indices(parent.children());
                 for ((ip_idx, replace_from_ip), (port_idx, replace_from_port)) in indices {
                     let replace_from = SocketAddr::new(replace_from_ip, replace_from_port.get());
-                    let replace_to_port = listen_for(
+                    let replace_to_port = match listen_for(
                         real_server_port,
                         ip_addr_from_real_server,
                         listen_port,
                         replace_from,
+                        options.clone(),
                     )
-                    .await;
+                    .await
+                    {
+                        Ok(port) => port,
+                        Err(err) => {
+                            output.error("tip", &err);
+                            continue;
+                        }
+                    };
                     replace_ip_port_pair(
                         parent.children_mut(),
                         ip_idx,
@@ -178,10 +169,34 @@ pub async fn pipe_pcp(
             }
             _ => {}
         }
+        for (rule, count) in options.rules.apply(output.direction(), &mut atom) {
+            output.info(&format!(
+                "Rewrite: Applied rule {} to {} atoms",
+                rule, count
+            ));
+        }
 
-        atom_stream_writer
-            .write(&atom)
-            .await
-            .map_err(PipeError::ByOutgoing)?;
+        let atoms = match output.script() {
+            Some(script) => {
+                let result = script.on_atom(&atom, output.direction());
+                for text in result.printed {
+                    output.info(&format!("Script: {}", text));
+                }
+                result.atoms.unwrap_or_else(|err| {
+                    let context = format!("passed {} as is", atom.to_identifier_string());
+                    output.error("script", &err.context(context));
+                    vec![atom]
+                })
+            }
+            None => vec![atom],
+        };
+        for atom in atoms {
+            let mut buf = Vec::new();
+            AtomStreamWriter::new(&mut buf)
+                .write_unknown_atom(&atom)
+                .await
+                .map_err(PipeError::ByOutgoing)?;
+            write_out(&mut outgoing, &buf, Some(&atom), output).await?;
+        }
     }
 }
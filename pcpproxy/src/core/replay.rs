@@ -0,0 +1,184 @@
+use std::{collections::BTreeMap, io::ErrorKind, num::NonZeroU16, time::Duration};
+
+use anyhow::Result;
+use peercastoxide_lib::net::{bind_dual_stack, canonical};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    spawn,
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    core::utils::io_error_kind,
+    features::{
+        capture::{CaptureRecord, Direction, RecordType},
+        output::ndjson::NDJson,
+    },
+};
+
+/// Records of each connection in the order they were captured.
+fn split_connections(records: Vec<CaptureRecord>) -> BTreeMap<u64, Vec<CaptureRecord>> {
+    let mut connections = BTreeMap::<_, Vec<_>>::new();
+    for record in records {
+        connections
+            .entry(record.connection)
+            .or_default()
+            .push(record);
+    }
+    connections
+}
+
+/// Writes the chunks sent in `direction`. Given the time the connection started, each chunk waits
+/// for as long after it as it was captured after the first record of the connection.
+async fn play(
+    writer: &mut (impl AsyncWrite + Unpin),
+    output: &NDJson,
+    records: &[CaptureRecord],
+    direction: Direction,
+    started_at: Option<Instant>,
+) -> Result<()> {
+    let first = records.first().map_or(0, |x| x.elapsed_micros);
+    for record in records.iter().filter(|x| x.direction == direction) {
+        if let Some(started_at) = started_at {
+            let offset = record.elapsed_micros.saturating_sub(first);
+            sleep_until(started_at + Duration::from_micros(offset)).await;
+        }
+        match record.r#type {
+            RecordType::Data => {
+                let data = record.bytes()?;
+                writer.write_all(&data).await?;
+                output.record(&data, None);
+            }
+            RecordType::Close => break,
+        }
+    }
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// Reads until the peer closes, counting the bytes as passed on.
+async fn drain(mut reader: impl AsyncRead + Unpin, output: &NDJson) -> Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        output.record(&buf[..n], None);
+    }
+}
+
+/// The error to output in `closed` for a direction which ended with `result`, after outputting it
+/// as an `error` event.
+fn close_reason(output: &NDJson, connection: u64, result: Result<()>) -> Option<ErrorKind> {
+    let err = result.err()?;
+    let error_kind = io_error_kind(&err).unwrap_or(ErrorKind::Other);
+    output.error("replay", &err.context(format!("connection {}", connection)));
+    Some(error_kind)
+}
+
+/// Plays the client side of each captured connection against `server_host`.
+pub async fn replay_to_server(
+    records: Vec<CaptureRecord>,
+    server_host: &str,
+    timing: bool,
+) -> Result<()> {
+    let started_at = Instant::now();
+    let tasks = split_connections(records)
+        .into_iter()
+        .map(|(connection, records)| {
+            let server_host = server_host.to_owned();
+            spawn(async move {
+                let (upload, download) = NDJson::connect(
+                    format!("replay#{}", connection),
+                    server_host.clone(),
+                    None,
+                    Default::default(),
+                );
+                let started_at = if timing {
+                    let first = records.first().map_or(0, |x| x.elapsed_micros);
+                    let started_at = started_at + Duration::from_micros(first);
+                    sleep_until(started_at).await;
+                    Some(started_at)
+                } else {
+                    None
+                };
+                let stream = match TcpStream::connect(&server_host).await {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        let error_kind = close_reason(&upload, connection, Err(err.into()));
+                        upload.disconnected_by_server(error_kind);
+                        download.disconnected_by_server(error_kind);
+                        return;
+                    }
+                };
+                let (reader, mut writer) = stream.into_split();
+                let received = spawn(async move {
+                    let result = drain(reader, &download).await;
+                    let error_kind = close_reason(&download, connection, result);
+                    download.disconnected_by_server(error_kind);
+                });
+                let result = play(
+                    &mut writer,
+                    &upload,
+                    &records,
+                    Direction::Upload,
+                    started_at,
+                )
+                .await;
+                let error_kind = close_reason(&upload, connection, result);
+                upload.disconnected_by_client(error_kind);
+                let _ = received.await;
+            })
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        task.await?;
+    }
+    Ok(())
+}
+
+/// Acts as the captured server: the n-th client to connect gets the n-th captured connection.
+pub async fn replay_as_server(
+    records: Vec<CaptureRecord>,
+    listen_port: NonZeroU16,
+    timing: bool,
+) -> Result<()> {
+    let listener = bind_dual_stack(listen_port.get())?;
+    let mut tasks = Vec::new();
+    for (connection, records) in split_connections(records) {
+        let (socket, client_addr) = listener.accept().await?;
+        tasks.push(spawn(async move {
+            let (upload, download) = NDJson::connect(
+                canonical(client_addr).to_string(),
+                format!("replay#{}", connection),
+                None,
+                Default::default(),
+            );
+            // The connection starts when the client connects instead of when it was captured.
+            let started_at = timing.then(Instant::now);
+            let (reader, mut writer) = socket.into_split();
+            let received = spawn(async move {
+                let result = drain(reader, &upload).await;
+                let error_kind = close_reason(&upload, connection, result);
+                upload.disconnected_by_client(error_kind);
+            });
+            let result = play(
+                &mut writer,
+                &download,
+                &records,
+                Direction::Download,
+                started_at,
+            )
+            .await;
+            let error_kind = close_reason(&download, connection, result);
+            download.disconnected_by_server(error_kind);
+            let _ = received.await;
+        }));
+    }
+    for task in tasks {
+        task.await?;
+    }
+    Ok(())
+}
@@ -1,12 +1,22 @@
-use std::io::{self, ErrorKind};
+use std::{
+    borrow::Cow,
+    io::{self, ErrorKind},
+    num::NonZeroU16,
+    str::FromStr,
+    time::Instant,
+};
+
+use anyhow::Context;
 
+use peercastoxide_lib::pcp::atom::UnknownAtom;
 use thiserror::Error;
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
     net::tcp::OwnedWriteHalf,
+    time::sleep,
 };
 
-use crate::features::output::ndjson::NDJson;
+use crate::features::{fault::corrupt_length, output::ndjson::NDJson};
 
 use super::pcp_proxy::pipe::big_vec;
 
@@ -18,10 +28,64 @@ pub enum PipeError {
     ByOutgoing(anyhow::Error),
 }
 
+async fn write_and_record(
+    outgoing: &mut OwnedWriteHalf,
+    data: &[u8],
+    atom: Option<&UnknownAtom>,
+    output: &NDJson,
+) -> Result<(), PipeError> {
+    outgoing
+        .write_all(data)
+        .await
+        .map_err(|err| PipeError::ByOutgoing(anyhow::Error::new(err)))?;
+    output.record(data, atom);
+    Ok(())
+}
+
+/// Writes bytes to pass on, which encode `atom` if any, with the faults injected into the
+/// direction.
+pub async fn write_out(
+    outgoing: &mut OwnedWriteHalf,
+    data: &[u8],
+    atom: Option<&UnknownAtom>,
+    output: &NDJson,
+) -> Result<(), PipeError> {
+    let Some(fault_injector) = output.fault_injector() else {
+        return write_and_record(outgoing, data, atom, output).await;
+    };
+    let injection = fault_injector.inject(data.len(), atom, Instant::now());
+    for event in &injection.events {
+        output.info(event);
+    }
+    if !injection.delay.is_zero() {
+        sleep(injection.delay).await;
+    }
+    if injection.drop {
+        return Ok(());
+    }
+    let mut data = Cow::Borrowed(data);
+    let mut atom = atom;
+    if let Some(noise) = injection.corrupt_length {
+        corrupt_length(data.to_mut(), noise);
+        atom = None;
+    }
+    if let Some(after) = injection.disconnect_after {
+        write_and_record(outgoing, &data[..after], None, output).await?;
+        let err = io::Error::new(ErrorKind::ConnectionAborted, "injected fault");
+        return Err(PipeError::ByOutgoing(anyhow::Error::new(err)));
+    }
+    write_and_record(outgoing, &data, atom, output).await?;
+    if injection.duplicate {
+        write_and_record(outgoing, &data, atom, output).await?;
+    }
+    Ok(())
+}
+
 async fn pipe_one_read(
     incoming: &mut (impl AsyncRead + Unpin),
     outgoing: &mut OwnedWriteHalf,
     buf: &mut [u8],
+    output: &NDJson,
 ) -> Result<bool, PipeError> {
     let n = incoming
         .read(buf)
@@ -30,10 +94,7 @@ async fn pipe_one_read(
     if n == 0 {
         return Ok(false);
     }
-    outgoing
-        .write_all(&buf[0..n])
-        .await
-        .map_err(|err| PipeError::ByOutgoing(anyhow::Error::new(err)))?;
+    write_out(outgoing, &buf[0..n], None, output).await?;
     Ok(true)
 }
 
@@ -43,12 +104,12 @@ pub async fn pipe_raw(
     output: &NDJson,
 ) -> Result<(), PipeError> {
     let mut buf = big_vec(1024 * 1024);
-    if !pipe_one_read(&mut incoming, &mut outgoing, &mut buf).await? {
+    if !pipe_one_read(&mut incoming, &mut outgoing, &mut buf, output).await? {
         return Ok(());
     }
     output.output_raw("(raw data stream)");
     loop {
-        if !pipe_one_read(&mut incoming, &mut outgoing, &mut buf).await? {
+        if !pipe_one_read(&mut incoming, &mut outgoing, &mut buf, output).await? {
             return Ok(());
         }
     }
@@ -58,58 +119,47 @@ pub fn io_error_kind(err: &anyhow::Error) -> Option<ErrorKind> {
     err.downcast_ref::<io::Error>().map(|err| err.kind())
 }
 
-pub fn disconnect_conn_of_upload(
-    result: Result<(), PipeError>,
-    output: NDJson,
-) -> anyhow::Result<()> {
+/// The error to output as the reason for closing. Errors other than I/O ones are output as
+/// `error` events as well.
+fn close_reason(err: &anyhow::Error, output: &NDJson) -> ErrorKind {
+    io_error_kind(err).unwrap_or_else(|| {
+        output.error("pipe", err);
+        ErrorKind::Other
+    })
+}
+
+pub fn disconnect_conn_of_upload(result: Result<(), PipeError>, output: NDJson) {
     match &result {
         Err(PipeError::ByIncoming(err)) => {
-            if let Some(error_kind) = io_error_kind(err) {
-                output.disconnected_by_client(Some(error_kind));
-                return Ok(());
-            }
-            eprintln!("Unknown error: {}", err);
-            result.map_err(anyhow::Error::new)
+            let error_kind = close_reason(err, &output);
+            output.disconnected_by_client(Some(error_kind));
         }
         Err(PipeError::ByOutgoing(err)) => {
-            if let Some(error_kind) = io_error_kind(err) {
-                output.disconnected_by_server(Some(error_kind));
-                return Ok(());
-            }
-            eprintln!("Unknown error: {}", err);
-            result.map_err(anyhow::Error::new)
-        }
-        Ok(_) => {
-            output.disconnected_by_client(None);
-            Ok(())
+            let error_kind = close_reason(err, &output);
+            output.disconnected_by_server(Some(error_kind));
         }
+        Ok(_) => output.disconnected_by_client(None),
     }
 }
 
-pub fn disconnect_conn_of_download(
-    result: Result<(), PipeError>,
-    output: NDJson,
-) -> anyhow::Result<()> {
+pub fn disconnect_conn_of_download(result: Result<(), PipeError>, output: NDJson) {
     match &result {
         Err(PipeError::ByIncoming(err)) => {
-            if let Some(error_kind) = io_error_kind(err) {
-                output.disconnected_by_server(Some(error_kind));
-                return Ok(());
-            }
-            eprintln!("Unknown error: {}", err);
-            result.map_err(anyhow::Error::new)
+            let error_kind = close_reason(err, &output);
+            output.disconnected_by_server(Some(error_kind));
         }
         Err(PipeError::ByOutgoing(err)) => {
-            if let Some(error_kind) = io_error_kind(err) {
-                output.disconnected_by_client(Some(error_kind));
-                return Ok(());
-            }
-            eprintln!("Unknown error: {}", err);
-            result.map_err(anyhow::Error::new)
-        }
-        Ok(_) => {
-            output.disconnected_by_server(None);
-            Ok(())
+            let error_kind = close_reason(err, &output);
+            output.disconnected_by_client(Some(error_kind));
         }
+        Ok(_) => output.disconnected_by_server(None),
     }
 }
+
+/// The port of `host:port`, including `[v6 address]:port`.
+pub fn port_of(host: &str) -> anyhow::Result<NonZeroU16> {
+    let (_, port) = host
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow::anyhow!("no port in {}", host))?;
+    NonZeroU16::from_str(port).with_context(|| format!("invalid port in {}", host))
+}
@@ -1,3 +1,7 @@
+pub mod capture;
+pub mod fault;
 pub mod output;
-pub mod pcp;
+pub mod pcapng;
 pub mod real_server_listener;
+pub mod rewrite;
+pub mod script;
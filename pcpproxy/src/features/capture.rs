@@ -0,0 +1,231 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use anyhow::{anyhow, Result};
+use peercastoxide_lib::pcp::atom::UnknownAtom;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{
+    output::atom_json::AtomJson,
+    pcapng::{PcapngConnection, PcapngWriter},
+};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+    /// From the client to the server
+    Upload,
+    /// From the server to the client
+    Download,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RecordType {
+    Data,
+    /// The sender closed its side of the connection.
+    Close,
+}
+
+/// A line of a capture file.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureRecord {
+    pub connection: u64,
+    pub direction: Direction,
+    pub r#type: RecordType,
+    /// Time since the capture started
+    pub elapsed_micros: u64,
+    /// Hex of the bytes as they were sent, after the proxy rewrote them
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub data: String,
+    /// The parsed atom for readers of the capture; replay uses `data` only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atom: Option<Value>,
+}
+
+impl CaptureRecord {
+    pub fn bytes(&self) -> Result<Vec<u8>> {
+        if !self.data.len().is_multiple_of(2) {
+            return Err(anyhow!("odd number of hex digits"));
+        }
+        // also rejects signs, which `from_str_radix` would take
+        if !self.data.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Err(anyhow!("data must be hex digits"));
+        }
+        self.data
+            .as_bytes()
+            .chunks(2)
+            .map(|x| Ok(u8::from_str_radix(std::str::from_utf8(x)?, 16)?))
+            .collect()
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// Writes every piped chunk of all connections to one file, a JSON record per line.
+pub struct CaptureWriter {
+    file: Mutex<BufWriter<File>>,
+    started_at: Instant,
+    last_connection: AtomicU64,
+}
+
+impl CaptureWriter {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            file: Mutex::new(BufWriter::new(File::create(path)?)),
+            started_at: Instant::now(),
+            last_connection: AtomicU64::new(0),
+        })
+    }
+
+    fn next_connection_id(&self) -> u64 {
+        self.last_connection.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn write(&self, record: &CaptureRecord) {
+        let mut file = self.file.lock().unwrap();
+        // Flushed per line so that the capture survives the proxy being killed.
+        let result = serde_json::to_writer(&mut *file, record)
+            .map_err(anyhow::Error::new)
+            .and_then(|_| Ok(writeln!(file)?))
+            .and_then(|_| Ok(file.flush()?));
+        if let Err(err) = result {
+            log::error!("capture: {}", err);
+        }
+    }
+}
+
+/// Where proxied connections are recorded.
+#[derive(Clone, Default)]
+pub struct Recorder {
+    pub capture: Option<Arc<CaptureWriter>>,
+    pub pcapng: Option<Arc<PcapngWriter>>,
+}
+
+impl Recorder {
+    /// Starts recording a proxied connection. Both of its directions share the returned handle.
+    pub fn connection(&self, client: SocketAddr, server: SocketAddr) -> Option<ConnectionCapture> {
+        if self.capture.is_none() && self.pcapng.is_none() {
+            return None;
+        }
+        Some(ConnectionCapture {
+            capture: self
+                .capture
+                .as_ref()
+                .map(|writer| (writer.clone(), writer.next_connection_id())),
+            pcapng: self
+                .pcapng
+                .as_ref()
+                .map(|writer| Arc::new(writer.connection(client, server))),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct ConnectionCapture {
+    capture: Option<(Arc<CaptureWriter>, u64)>,
+    pcapng: Option<Arc<PcapngConnection>>,
+}
+
+impl ConnectionCapture {
+    fn record(&self, direction: Direction, r#type: RecordType, data: &[u8], atom: Option<Value>) {
+        let Some((writer, connection)) = &self.capture else {
+            return;
+        };
+        writer.write(&CaptureRecord {
+            connection: *connection,
+            direction,
+            r#type,
+            elapsed_micros: writer.started_at.elapsed().as_micros() as u64,
+            data: to_hex(data),
+            atom,
+        });
+    }
+
+    pub fn data(&self, direction: Direction, data: &[u8], atom: Option<&UnknownAtom>) {
+        if let Some(pcapng) = &self.pcapng {
+            pcapng.data(direction, data);
+        }
+        let atom = atom.map(|x| json!(AtomJson::new(x)));
+        self.record(direction, RecordType::Data, data, atom);
+    }
+
+    pub fn close(&self, direction: Direction) {
+        if let Some(pcapng) = &self.pcapng {
+            pcapng.close(direction);
+        }
+        self.record(direction, RecordType::Close, &[], None);
+    }
+}
+
+pub fn read_capture(path: impl AsRef<Path>) -> Result<Vec<CaptureRecord>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(i, line)| {
+            serde_json::from_str(&line?).map_err(|err| anyhow!("line {}: {}", i + 1, err))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use peercastoxide_lib::pcp::atom::{well_known_identifiers::PORT, UnknownAtom};
+
+    use super::{read_capture, CaptureRecord, CaptureWriter, Direction, RecordType, Recorder};
+
+    #[test]
+    fn test_capture() {
+        let path = std::env::temp_dir().join(format!("pcpproxy-capture-{}", std::process::id()));
+        let recorder = Recorder {
+            capture: Some(Arc::new(CaptureWriter::create(&path).unwrap())),
+            pcapng: None,
+        };
+        let addr = "127.0.0.1:7144".parse().unwrap();
+        let first = recorder.connection(addr, addr).unwrap();
+        let second = recorder.connection(addr, addr).unwrap();
+        let atom = UnknownAtom::u16(PORT, 7144);
+        first.data(Direction::Upload, b"pcp\n", None);
+        second.data(Direction::Download, &[1, 2, 0xff], Some(&atom));
+        first.close(Direction::Upload);
+        drop((first, second, recorder));
+
+        let records = read_capture(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].connection, 1);
+        assert_eq!(records[0].bytes().unwrap(), b"pcp\n");
+        assert_eq!(records[1].connection, 2);
+        assert_eq!(records[1].direction, Direction::Download);
+        assert_eq!(records[1].bytes().unwrap(), [1, 2, 0xff]);
+        assert_eq!(records[1].atom.as_ref().unwrap()["payload"], 7144);
+        assert_eq!(records[2].r#type, RecordType::Close);
+        assert!(records[2].elapsed_micros >= records[0].elapsed_micros);
+
+        let record = |data: &str| CaptureRecord {
+            data: data.into(),
+            ..records[0].clone()
+        };
+        assert_eq!(record("00aBff").bytes().unwrap(), [0, 0xab, 0xff]);
+        assert!(record("0").bytes().is_err());
+        assert!(record("+f").bytes().is_err());
+        // a multibyte character across two pairs
+        assert!(record("0\u{e9}0").bytes().is_err());
+    }
+}
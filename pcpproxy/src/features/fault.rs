@@ -0,0 +1,388 @@
+//! Faults injected into proxied streams, declared in a JSON file.
+//!
+//! The file is an array of faults such as:
+//!
+//! ```json
+//! [
+//!   { "connection": "pcp", "direction": "download", "fault": "delay", "path": "chan/pkt", "millis": 200 },
+//!   { "fault": "drop", "path": "bcst", "probability": 0.5 },
+//!   { "direction": "upload", "fault": "throttle", "bytesPerSecond": 8000 },
+//!   { "connection": "raw", "fault": "disconnect", "probability": 0.001 },
+//!   { "fault": "corruptLength", "path": "chan/pkt", "probability": 0.01 },
+//!   { "fault": "duplicate", "path": "chan/pkt", "probability": 0.1 }
+//! ]
+//! ```
+//!
+//! `connection` is the type classified from the first bytes (`http`, `pcp` or `raw`), and
+//! `direction` is `upload` or `download`; both match any when omitted. Faults apply to what the pipe
+//! writes at a time: an atom, a chunk of a raw stream, or a line of HTTP headers. Faults with a
+//! `path` apply only to atoms at the identifier path, and the others to everything.
+//! `probability` defaults to 1.
+
+use std::{
+    fs,
+    num::NonZeroU64,
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use peercastoxide_lib::pcp::atom::UnknownAtom;
+use rand::{Rng, RngCore};
+use serde::Deserialize;
+
+use super::{capture::Direction, output::filter::AtomPath};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ConnectionType {
+    Http,
+    Pcp,
+    Raw,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(tag = "fault", rename_all = "camelCase")]
+enum FaultKind {
+    #[serde(rename_all = "camelCase")]
+    Delay {
+        millis: u64,
+    },
+    Drop,
+    Duplicate,
+    /// Writes a wrong length in the header of the atom.
+    CorruptLength,
+    /// Writes a part and closes the direction.
+    Disconnect,
+    #[serde(rename_all = "camelCase")]
+    Throttle {
+        bytes_per_second: NonZeroU64,
+    },
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FaultJson {
+    connection: Option<ConnectionType>,
+    direction: Option<Direction>,
+    path: Option<String>,
+    #[serde(default = "one")]
+    probability: f64,
+    #[serde(flatten)]
+    kind: FaultKind,
+}
+
+#[derive(Clone, Debug)]
+struct Fault {
+    connection: Option<ConnectionType>,
+    direction: Option<Direction>,
+    path: Option<AtomPath>,
+    probability: f64,
+    kind: FaultKind,
+}
+
+impl Fault {
+    fn parse(json: FaultJson) -> Result<Self> {
+        if !(0.0..=1.0).contains(&json.probability) {
+            return Err(anyhow!("probability must be between 0 and 1"));
+        }
+        if json.path.is_some() && matches!(json.kind, FaultKind::Throttle { .. }) {
+            return Err(anyhow!("throttle applies to the whole direction"));
+        }
+        Ok(Self {
+            connection: json.connection,
+            direction: json.direction,
+            path: json.path.as_deref().map(str::parse).transpose()?,
+            probability: json.probability,
+            kind: json.kind,
+        })
+    }
+
+    fn hits(&self, atom: Option<&UnknownAtom>, rng: &mut impl Rng) -> bool {
+        let on_path = match (&self.path, atom) {
+            (None, _) => true,
+            (Some(path), Some(atom)) => path.reaches(0, atom),
+            (Some(_), None) => false,
+        };
+        on_path && rng.gen::<f64>() < self.probability
+    }
+}
+
+#[derive(Default)]
+pub struct FaultConfig(Vec<Fault>);
+
+impl FromStr for FaultConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let faults: Vec<FaultJson> = serde_json::from_str(s)?;
+        faults
+            .into_iter()
+            .enumerate()
+            .map(|(i, json)| Fault::parse(json).map_err(|err| anyhow!("fault {}: {}", i + 1, err)))
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+impl FaultConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// The faults for a direction of a connection, if any, rolled with `rng`.
+    pub fn injector(
+        &self,
+        connection: ConnectionType,
+        direction: Direction,
+        rng: Box<dyn RngCore + Send>,
+    ) -> Option<FaultInjector> {
+        let faults = self
+            .0
+            .iter()
+            .filter(|x| x.connection.is_none_or(|x| x == connection))
+            .filter(|x| x.direction.is_none_or(|x| x == direction))
+            .cloned()
+            .collect::<Vec<_>>();
+        if faults.is_empty() {
+            return None;
+        }
+        Some(FaultInjector {
+            faults,
+            throttled_until: Mutex::new(None),
+            rng: Mutex::new(rng),
+        })
+    }
+}
+
+/// What to do with something the pipe writes.
+#[derive(Debug, Default)]
+pub struct Injection {
+    pub delay: Duration,
+    pub drop: bool,
+    pub duplicate: bool,
+    /// What to XOR into the length of the atom
+    pub corrupt_length: Option<u32>,
+    /// How many bytes to write before closing the direction
+    pub disconnect_after: Option<usize>,
+    /// Messages for the output
+    pub events: Vec<String>,
+}
+
+pub struct FaultInjector {
+    faults: Vec<Fault>,
+    /// When the bytes written so far have passed the throttle
+    throttled_until: Mutex<Option<Instant>>,
+    rng: Mutex<Box<dyn RngCore + Send>>,
+}
+
+impl FaultInjector {
+    /// The slowest of the throttles
+    fn bytes_per_second(&self) -> Option<NonZeroU64> {
+        self.faults
+            .iter()
+            .filter_map(|x| match x.kind {
+                FaultKind::Throttle { bytes_per_second } => Some(bytes_per_second),
+                _ => None,
+            })
+            .min()
+    }
+
+    /// Messages to output when the direction starts
+    pub fn describe(&self) -> Vec<String> {
+        self.bytes_per_second()
+            .map(|x| format!("Fault: Throttling to {} bytes/s", x))
+            .into_iter()
+            .collect()
+    }
+
+    /// Rolls the faults for `len` bytes about to be written, encoding `atom` if any.
+    pub fn inject(&self, len: usize, atom: Option<&UnknownAtom>, now: Instant) -> Injection {
+        let name = atom.map_or_else(
+            || format!("{} bytes", len),
+            |x| format!("{} atom", x.to_identifier_string()),
+        );
+        let mut injection = Injection::default();
+        let mut rng = self.rng.lock().unwrap();
+        for fault in &self.faults {
+            if matches!(fault.kind, FaultKind::Throttle { .. }) || !fault.hits(atom, &mut *rng) {
+                continue;
+            }
+            match fault.kind {
+                FaultKind::Delay { millis } => {
+                    injection.delay += Duration::from_millis(millis);
+                    let event = format!("Fault: Delayed {} by {} ms", name, millis);
+                    injection.events.push(event);
+                }
+                FaultKind::Drop => {
+                    injection.drop = true;
+                    injection.events.push(format!("Fault: Dropped {}", name));
+                }
+                FaultKind::Duplicate => {
+                    injection.duplicate = true;
+                    injection.events.push(format!("Fault: Duplicated {}", name));
+                }
+                FaultKind::CorruptLength if atom.is_some() => {
+                    injection.corrupt_length = Some(rng.gen_range(1..0x8000_0000));
+                    let event = format!("Fault: Corrupted the length of {}", name);
+                    injection.events.push(event);
+                }
+                FaultKind::CorruptLength => {}
+                FaultKind::Disconnect => {
+                    let after = if len > 1 { rng.gen_range(1..len) } else { 0 };
+                    injection.disconnect_after = Some(after);
+                    let event = format!("Fault: Disconnected after {} bytes of {}", after, name);
+                    injection.events.push(event);
+                }
+                FaultKind::Throttle { .. } => unreachable!(),
+            }
+        }
+        if injection.drop {
+            return injection;
+        }
+        let written = match injection.disconnect_after {
+            Some(after) => after,
+            None if injection.duplicate => len * 2,
+            None => len,
+        };
+        if let Some(bytes_per_second) = self.bytes_per_second() {
+            let mut throttled_until = self.throttled_until.lock().unwrap();
+            let start = throttled_until.map_or(now, |x| x.max(now));
+            injection.delay += start - now;
+            let micros = written as u64 * 1_000_000 / bytes_per_second.get();
+            *throttled_until = Some(start + Duration::from_micros(micros));
+        }
+        injection
+    }
+}
+
+/// Replaces the length in the header of an atom with a wrong one, XORed with non-zero `noise`.
+pub fn corrupt_length(data: &mut [u8], noise: u32) {
+    if data.len() < 8 {
+        return;
+    }
+    let mut length = [0; 4];
+    length.copy_from_slice(&data[4..8]);
+    // Keeps the parent flag so that the atom is still read as the same kind.
+    let length = u32::from_le_bytes(length);
+    let corrupted = (length & 0x8000_0000) | ((length ^ noise) & 0x7fff_ffff);
+    data[4..8].copy_from_slice(&corrupted.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use peercastoxide_lib::pcp::atom::{
+        well_known_identifiers::{BCST, CHAN},
+        UnknownAtom,
+    };
+    use rand::rngs::mock::StepRng;
+
+    use crate::features::capture::Direction;
+
+    use super::{corrupt_length, ConnectionType, FaultConfig};
+
+    /// Rolls 0.0, which hits every fault
+    fn hit() -> Box<StepRng> {
+        Box::new(StepRng::new(0, 0))
+    }
+
+    /// Rolls just under 1.0, which misses every fault with a probability below 1
+    fn miss() -> Box<StepRng> {
+        Box::new(StepRng::new(u64::MAX, 0))
+    }
+
+    #[test]
+    fn test_fault() {
+        let config: FaultConfig = r#"[
+            { "connection": "pcp", "fault": "drop", "path": "bcst" },
+            { "direction": "download", "fault": "delay", "millis": 200 },
+            { "fault": "throttle", "bytesPerSecond": 1000 }
+        ]"#
+        .parse()
+        .unwrap();
+
+        let injector = config
+            .injector(ConnectionType::Pcp, Direction::Upload, miss())
+            .unwrap();
+        let now = Instant::now();
+        let bcst = UnknownAtom::parent(BCST, vec![]);
+        let injection = injector.inject(500, Some(&bcst), now);
+        assert!(injection.drop);
+        assert_eq!(injection.delay, Duration::ZERO);
+        assert_eq!(injection.events, ["Fault: Dropped bcst atom"]);
+        let chan = UnknownAtom::parent(CHAN, vec![]);
+        let injection = injector.inject(500, Some(&chan), now);
+        assert!(!injection.drop);
+        assert_eq!(injection.delay, Duration::ZERO);
+        let injection = injector.inject(500, Some(&chan), now);
+        assert_eq!(injection.delay, Duration::from_millis(500));
+
+        let injector = config
+            .injector(ConnectionType::Raw, Direction::Download, miss())
+            .unwrap();
+        let injection = injector.inject(10, None, now);
+        assert!(!injection.drop);
+        assert_eq!(injection.delay, Duration::from_millis(200));
+
+        let mut data = [b'c', b'h', b'a', b'n', 0, 0, 0, 0x80];
+        corrupt_length(&mut data, 0x1234);
+        assert_eq!(data[4..8], [0x34, 0x12, 0, 0x80]);
+    }
+
+    #[test]
+    fn test_fault_probability() {
+        let config: FaultConfig = r#"[
+            { "fault": "duplicate", "path": "chan", "probability": 0.5 },
+            { "connection": "raw", "fault": "disconnect", "probability": 0.001 },
+            { "connection": "pcp", "fault": "corruptLength", "probability": 0.01 }
+        ]"#
+        .parse()
+        .unwrap();
+        let now = Instant::now();
+        let chan = UnknownAtom::parent(CHAN, vec![]);
+
+        let injector = config
+            .injector(ConnectionType::Pcp, Direction::Download, miss())
+            .unwrap();
+        let injection = injector.inject(500, Some(&chan), now);
+        assert!(!injection.duplicate);
+        assert_eq!(injection.corrupt_length, None);
+        assert!(injection.events.is_empty());
+
+        let injector = config
+            .injector(ConnectionType::Pcp, Direction::Download, hit())
+            .unwrap();
+        let injection = injector.inject(500, Some(&chan), now);
+        assert!(injection.duplicate);
+        assert_eq!(injection.corrupt_length, Some(1));
+        assert_eq!(
+            injection.events,
+            [
+                "Fault: Duplicated chan atom",
+                "Fault: Corrupted the length of chan atom"
+            ]
+        );
+
+        let injector = config
+            .injector(ConnectionType::Raw, Direction::Upload, miss())
+            .unwrap();
+        assert_eq!(injector.inject(10, None, now).disconnect_after, None);
+        let injector = config
+            .injector(ConnectionType::Raw, Direction::Upload, hit())
+            .unwrap();
+        let injection = injector.inject(10, None, now);
+        assert_eq!(injection.disconnect_after, Some(1));
+        assert_eq!(
+            injection.events,
+            ["Fault: Disconnected after 1 bytes of 10 bytes"]
+        );
+    }
+}
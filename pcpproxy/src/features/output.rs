@@ -1 +1,3 @@
+pub mod atom_json;
+pub mod filter;
 pub mod ndjson;
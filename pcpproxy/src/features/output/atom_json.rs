@@ -0,0 +1,184 @@
+use derive_new::new;
+use peercastoxide_lib::pcp::atom::{well_known_identifiers::*, AtomChild, AtomParent, UnknownAtom};
+use serde::{ser::SerializeMap, Serialize};
+
+fn from_flg1_to_string(data: u8) -> String {
+    let tracker = data & 1 << 0 != 0;
+    let relay = data & 1 << 1 != 0;
+    let direct = data & 1 << 2 != 0;
+    let push = data & 1 << 3 != 0;
+    let recv = data & 1 << 4 != 0;
+    let cin = data & 1 << 5 != 0;
+    let private = data & 1 << 6 != 0;
+    let unused = data & 1 << 7 != 0;
+    format!(
+        "{}tracker{}{}relay{}{}direct{}{}push{}{}recv{}{}cin{}{}private{}{}?{}",
+        if tracker { '[' } else { ' ' },
+        if tracker { ']' } else { ' ' },
+        if relay { '[' } else { ' ' },
+        if relay { ']' } else { ' ' },
+        if direct { '[' } else { ' ' },
+        if direct { ']' } else { ' ' },
+        if push { '[' } else { ' ' },
+        if push { ']' } else { ' ' },
+        if recv { '[' } else { ' ' },
+        if recv { ']' } else { ' ' },
+        if cin { '[' } else { ' ' },
+        if cin { ']' } else { ' ' },
+        if private { '[' } else { ' ' },
+        if private { ']' } else { ' ' },
+        if unused { '[' } else { ' ' },
+        if unused { ']' } else { ' ' },
+    )
+}
+
+/// The JSON form of an atom in the `atom` lines of the output.
+#[derive(new)]
+pub struct AtomJson<'a> {
+    atom: &'a UnknownAtom,
+    /// How many bytes of `data` payloads are written as hex
+    #[new(default)]
+    data_preview: usize,
+}
+
+impl AtomJson<'_> {
+    pub fn with_data_preview(self, data_preview: usize) -> Self {
+        Self {
+            data_preview,
+            ..self
+        }
+    }
+}
+
+impl Serialize for AtomJson<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self.atom {
+            UnknownAtom::Parent(parent) => serialize_parent(parent, self.data_preview, serializer),
+            UnknownAtom::Child(child) => serialize_child(child, self.data_preview, serializer),
+        }
+    }
+}
+
+fn serialize_parent<S: serde::Serializer>(
+    parent: &AtomParent,
+    data_preview: usize,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(2))?;
+    map.serialize_entry(
+        "identifier",
+        &String::from_utf8_lossy(parent.identifier().0.as_ref()),
+    )?;
+    let children = parent
+        .children()
+        .iter()
+        .map(|x| AtomJson::new(x).with_data_preview(data_preview))
+        .collect::<Vec<_>>();
+    map.serialize_entry("children", &children)?;
+    map.end()
+}
+
+fn serialize_child<S: serde::Serializer>(
+    child: &AtomChild,
+    data_preview: usize,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(2))?;
+    let identifier = child.identifier().0.as_ref();
+    map.serialize_entry("identifier", &String::from_utf8_lossy(identifier))?;
+
+    let data = child.data();
+    match identifier {
+        PORT | UPPT | VEXP | VEXN if data.len() == 2 => {
+            map.serialize_entry("payload", &child.to_u16().unwrap())?;
+        }
+        BITR | NEWP | NUML | NUMR | OK | OLDP | POS | QUIT | UPHP | UPPT | UPTM | VER | VERS
+        | VEVP | VRVP
+            if data.len() == 4 =>
+        {
+            map.serialize_entry("payload", &child.to_u32().unwrap())?;
+        }
+        IP | RIP | UPIP if data.len() == 4 || data.len() == 16 => {
+            map.serialize_entry("payload", &child.to_ip().unwrap().to_string())?;
+        }
+        CID | FROM | ID | SID if data.len() == 16 => {
+            let value = data
+                .iter()
+                .map(|&x| format!("{:x}", x))
+                .collect::<Vec<_>>()
+                .join("");
+            map.serialize_entry("payload", &value)?;
+        }
+        AGNT | ALBM | CMNT | CREA | DESC | GNRE | NAME | STYP | SEXT | TITL | TYPE | URL => {
+            map.serialize_entry("payload", &String::from_utf8_lossy(data))?;
+        }
+        DATA if data_preview == 0 => {
+            map.serialize_entry("payload", &format!("({} bytes)", data.len()))?;
+        }
+        DATA => {
+            let hex = data
+                .iter()
+                .take(data_preview)
+                .map(|x| format!("{:02x}", x))
+                .collect::<String>();
+            let ellipsis = if data.len() > data_preview { "..." } else { "" };
+            let value = format!("{}{} ({} bytes)", hex, ellipsis, data.len());
+            map.serialize_entry("payload", &value)?;
+        }
+        FLG1 if data.len() == 1 => {
+            map.serialize_entry("payload", &from_flg1_to_string(data[0]))?;
+        }
+        _ => {
+            map.serialize_entry("payload", data)?;
+        }
+    };
+    map.end()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use peercastoxide_lib::pcp::atom::{
+        well_known_identifiers::{DATA, FLG1, HOST, IP, PCP, PORT, SID},
+        UnknownAtom,
+    };
+    use serde_json::json;
+
+    use super::AtomJson;
+
+    #[test]
+    fn test_atom_json() {
+        let atom = UnknownAtom::parent(
+            HOST,
+            vec![
+                UnknownAtom::child(SID, (0..16).collect()),
+                UnknownAtom::ipv4(IP, &Ipv4Addr::new(192, 168, 0, 1)),
+                UnknownAtom::u16(PORT, 7144),
+                UnknownAtom::u8(FLG1, 0b11),
+                UnknownAtom::child(DATA, vec![0; 3]),
+                UnknownAtom::u32(PCP, 1),
+            ],
+        );
+        assert_eq!(
+            json!(AtomJson::new(&atom)),
+            json!({
+                "identifier": "host",
+                "children": [
+                    { "identifier": "sid\0", "payload": "0123456789abcdef" },
+                    { "identifier": "ip\0\0", "payload": "192.168.0.1" },
+                    { "identifier": "port", "payload": 7144 },
+                    { "identifier": "flg1", "payload": "[tracker][relay] direct  push  recv  cin  private  ? " },
+                    { "identifier": "data", "payload": "(3 bytes)" },
+                    { "identifier": "pcp\n", "payload": [1, 0, 0, 0] },
+                ],
+            })
+        );
+        let data = UnknownAtom::child(DATA, vec![0xab; 5]);
+        let json = json!(AtomJson::new(&data).with_data_preview(2));
+        assert_eq!(json["payload"], "abab... (5 bytes)");
+    }
+}
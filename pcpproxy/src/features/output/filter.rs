@@ -0,0 +1,260 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    num::NonZeroU32,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use peercastoxide_lib::pcp::atom::UnknownAtom;
+
+/// `/`-separated identifiers from a top-level atom down, e.g. `chan/pkt/data`. `*` matches any
+/// identifier.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AtomPath(Vec<Option<String>>);
+
+impl FromStr for AtomPath {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let segments = s
+            .split('/')
+            .map(|segment| match segment {
+                "" => Err(anyhow!("empty identifier in {:?}", s)),
+                "*" => Ok(None),
+                _ if segment.len() > 4 => Err(anyhow!("identifier longer than 4 bytes in {:?}", s)),
+                _ => Ok(Some(segment.to_owned())),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self(segments))
+    }
+}
+
+impl AtomPath {
+    /// Whether `atom` at `depth` is on this path.
+    pub fn matches_at(&self, depth: usize, atom: &UnknownAtom) -> bool {
+        match self.0.get(depth) {
+            Some(Some(identifier)) => *identifier == atom.to_identifier_string(),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    pub fn is_last(&self, depth: usize) -> bool {
+        depth + 1 == self.0.len()
+    }
+
+    /// Whether `atom` at `depth` is the end of this path.
+    fn ends_at(&self, depth: usize, atom: &UnknownAtom) -> bool {
+        self.is_last(depth) && self.matches_at(depth, atom)
+    }
+
+    /// Whether this path leads to `atom` or to one of its descendants.
+    pub fn reaches(&self, depth: usize, atom: &UnknownAtom) -> bool {
+        if !self.matches_at(depth, atom) {
+            return false;
+        }
+        if self.is_last(depth) {
+            return true;
+        }
+        match atom {
+            UnknownAtom::Parent(parent) => {
+                parent.children().iter().any(|x| self.reaches(depth + 1, x))
+            }
+            UnknownAtom::Child(_) => false,
+        }
+    }
+}
+
+/// Which atoms are written to the output and how, shared by all connections.
+#[derive(Debug, Default)]
+pub struct OutputFilter {
+    /// When not empty, only the top-level atoms in which one of these paths exists are written.
+    pub include: Vec<AtomPath>,
+    /// Atoms at these paths are left out along with their children.
+    pub exclude: Vec<AtomPath>,
+    /// How many bytes of `data` payloads are written as hex
+    pub data_preview: usize,
+    /// How many atoms of the same shape a connection writes per second in each direction
+    pub rate_limit: Option<NonZeroU32>,
+}
+
+impl OutputFilter {
+    /// The part of `atom` to write, if any.
+    pub fn apply<'a>(&self, atom: &'a UnknownAtom) -> Option<Cow<'a, UnknownAtom>> {
+        if !self.include.is_empty() && !self.include.iter().any(|x| x.reaches(0, atom)) {
+            return None;
+        }
+        if self.exclude.is_empty() {
+            return Some(Cow::Borrowed(atom));
+        }
+        self.exclude_from(0, atom).map(Cow::Owned)
+    }
+
+    fn exclude_from(&self, depth: usize, atom: &UnknownAtom) -> Option<UnknownAtom> {
+        if self.exclude.iter().any(|x| x.ends_at(depth, atom)) {
+            return None;
+        }
+        match atom {
+            UnknownAtom::Parent(parent) => Some(UnknownAtom::parent(
+                parent.identifier().clone(),
+                parent
+                    .children()
+                    .iter()
+                    .filter_map(|x| self.exclude_from(depth + 1, x))
+                    .collect(),
+            )),
+            UnknownAtom::Child(_) => Some(atom.clone()),
+        }
+    }
+}
+
+/// The identifier paths in `atom`, which repeated atoms such as `chan/pkt/data` share.
+fn shape(atom: &UnknownAtom) -> String {
+    fn push_paths(prefix: &str, atom: &UnknownAtom, paths: &mut Vec<String>) {
+        let path = format!("{}{}", prefix, atom.to_identifier_string());
+        if let UnknownAtom::Parent(parent) = atom {
+            for child in parent.children() {
+                push_paths(&format!("{}/", path), child, paths);
+            }
+        }
+        paths.push(path);
+    }
+    let mut paths = Vec::new();
+    push_paths("", atom, &mut paths);
+    paths.sort();
+    paths.dedup();
+    paths.join(" ")
+}
+
+struct Window {
+    identifier: String,
+    started_at: Instant,
+    written: u32,
+    suppressed: u64,
+}
+
+/// Counts atoms of each shape for a direction of a connection.
+pub struct RateLimiter {
+    limit: NonZeroU32,
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: NonZeroU32) -> Self {
+        Self {
+            limit,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `None` if `atom` should be suppressed, otherwise how many atoms of its shape were suppressed
+    /// since the last one written.
+    pub fn check(&self, atom: &UnknownAtom, now: Instant) -> Option<u64> {
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows.entry(shape(atom)).or_insert_with(|| Window {
+            identifier: atom.to_identifier_string(),
+            started_at: now,
+            written: 0,
+            suppressed: 0,
+        });
+        let mut suppressed = 0;
+        if now.duration_since(window.started_at) >= Duration::from_secs(1) {
+            suppressed = std::mem::take(&mut window.suppressed);
+            window.started_at = now;
+            window.written = 0;
+        }
+        if window.written >= self.limit.get() {
+            window.suppressed += 1;
+            return None;
+        }
+        window.written += 1;
+        Some(suppressed)
+    }
+
+    /// Identifiers of the atoms suppressed since the last one of their shape was written, and how
+    /// many.
+    pub fn take_suppressed(&self) -> Vec<(String, u64)> {
+        let mut windows = self.windows.lock().unwrap();
+        let mut list = windows
+            .values_mut()
+            .filter(|window| window.suppressed > 0)
+            .map(|window| {
+                let suppressed = std::mem::take(&mut window.suppressed);
+                (window.identifier.clone(), suppressed)
+            })
+            .collect::<Vec<_>>();
+        list.sort();
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroU32,
+        time::{Duration, Instant},
+    };
+
+    use peercastoxide_lib::pcp::atom::{
+        well_known_identifiers::{CHAN, DATA, ID, PKT, POS, TYPE},
+        UnknownAtom,
+    };
+
+    use super::{OutputFilter, RateLimiter};
+
+    fn chan_pkt(pos: u32, data: bool) -> UnknownAtom {
+        let mut pkt = vec![
+            UnknownAtom::str(TYPE, "data").unwrap(),
+            UnknownAtom::u32(POS, pos),
+        ];
+        if data {
+            pkt.push(UnknownAtom::child(DATA, vec![0; 100]));
+        }
+        UnknownAtom::parent(
+            CHAN,
+            vec![
+                UnknownAtom::child(ID, vec![0; 16]),
+                UnknownAtom::parent(PKT, pkt),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_filter() {
+        let atom = chan_pkt(0, true);
+        let filter = OutputFilter {
+            exclude: vec!["chan/pkt/data".parse().unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(*filter.apply(&atom).unwrap(), chan_pkt(0, false));
+
+        let filter = OutputFilter {
+            include: vec!["chan/*/pos".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(filter.apply(&atom).is_some());
+        let filter = OutputFilter {
+            include: vec!["bcst".parse().unwrap(), "chan/info".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(filter.apply(&atom).is_none());
+        assert!("chan//data".parse::<super::AtomPath>().is_err());
+
+        let limiter = RateLimiter::new(NonZeroU32::new(2).unwrap());
+        let now = Instant::now();
+        assert_eq!(limiter.check(&chan_pkt(1, true), now), Some(0));
+        assert_eq!(limiter.check(&chan_pkt(2, true), now), Some(0));
+        assert_eq!(limiter.check(&chan_pkt(3, true), now), None);
+        assert_eq!(limiter.check(&UnknownAtom::child(ID, vec![]), now), Some(0));
+        assert_eq!(limiter.check(&chan_pkt(4, true), now), None);
+        assert_eq!(limiter.take_suppressed(), [("chan".to_owned(), 2)]);
+        assert_eq!(limiter.check(&chan_pkt(4, true), now), None);
+        assert_eq!(limiter.check(&chan_pkt(4, true), now), None);
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.check(&chan_pkt(5, true), later), Some(2));
+        assert!(limiter.take_suppressed().is_empty());
+    }
+}
@@ -1,29 +1,156 @@
-use std::{borrow::Cow, io::ErrorKind};
+//! Events of proxied connections, a JSON object per line on stdout.
+//!
+//! Every line of schema version 1 has these fields:
+//!
+//! - `schemaVersion`: [`SCHEMA_VERSION`]
+//! - `connectionId`: a UUID shared by both directions of a connection
+//! - `seq`: the order of the event within the connection, starting from 0
+//! - `time`: wall-clock milliseconds since the Unix epoch
+//! - `monotonicMicros`: microseconds since the first event of the process
+//! - `clientHost`, `serverHost`
+//! - `direction`: `upload` (from the client) or `download` (from the server)
+//! - `offset`: bytes passed on in `direction` before the event
+//! - `type` and `payload`:
+//!   - `connected`: `null`, once per connection before any other event
+//!   - `atom`: the atom read from the sender
+//!   - `raw`: text of HTTP headers or non-PCP streams
+//!   - `info`: a message from the proxy
+//!   - `closed`: `{ "by": "client" | "server", "error": string | null, "bytes": number }`, once
+//!     per direction
+//!   - `error`: `{ "context": string, "message": string }`, a failure the proxy carried on after
+//!
+//! `error` events outside a connection, e.g. when connecting to the server fails, have `null` for
+//! `connectionId`, `seq`, `direction`, `offset` and the hosts not known.
 
+use std::{
+    io::{ErrorKind, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use once_cell::sync::Lazy;
+use peercastoxide_lib::pcp::atom::UnknownAtom;
+use rand::{rngs::StdRng, SeedableRng};
 use serde_json::{json, Value};
+use uuid::Uuid;
 
-use crate::features::pcp::atom::Atom;
+use crate::features::{
+    capture::{ConnectionCapture, Direction},
+    fault::{ConnectionType, FaultConfig, FaultInjector},
+    script::ScriptConnection,
+};
 
-pub struct NDJson {
+use super::{
+    atom_json::AtomJson,
+    filter::{OutputFilter, RateLimiter},
+};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+static STARTED_AT: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// What both directions of a connection share.
+struct ConnectionLog {
+    id: String,
     client_host: String,
     server_host: String,
+    next_seq: AtomicU64,
+}
+
+pub struct NDJson {
+    connection: Arc<ConnectionLog>,
     upload: bool,
+    offset: AtomicU64,
+    capture: Option<ConnectionCapture>,
+    filter: Arc<OutputFilter>,
+    rate_limiter: Option<RateLimiter>,
+    fault_injector: Option<FaultInjector>,
+    script: Option<ScriptConnection>,
 }
 
 impl NDJson {
-    pub fn upload(client_host: String, server_host: String) -> Self {
+    fn new(
+        connection: Arc<ConnectionLog>,
+        upload: bool,
+        capture: Option<ConnectionCapture>,
+        filter: Arc<OutputFilter>,
+    ) -> Self {
         Self {
-            client_host,
-            server_host,
-            upload: true,
+            connection,
+            upload,
+            offset: AtomicU64::new(0),
+            capture,
+            rate_limiter: filter.rate_limit.map(RateLimiter::new),
+            filter,
+            fault_injector: None,
+            script: None,
         }
     }
 
-    pub fn download(client_host: String, server_host: String) -> Self {
-        Self {
+    /// Outputs the `connected` event and returns the outputs for upload and download.
+    pub fn connect(
+        client_host: String,
+        server_host: String,
+        capture: Option<ConnectionCapture>,
+        filter: Arc<OutputFilter>,
+    ) -> (Self, Self) {
+        let connection = Arc::new(ConnectionLog {
+            id: Uuid::new_v4().to_string(),
             client_host,
             server_host,
-            upload: false,
+            next_seq: AtomicU64::new(0),
+        });
+        let upload = Self::new(connection.clone(), true, capture.clone(), filter.clone());
+        upload.output_internal("connected", Value::Null);
+        (upload, Self::new(connection, false, capture, filter))
+    }
+
+    pub fn with_faults(self, faults: &FaultConfig, connection: ConnectionType) -> Self {
+        let rng = Box::new(StdRng::from_entropy());
+        let fault_injector = faults.injector(connection, self.direction(), rng);
+        for event in fault_injector.iter().flat_map(|x| x.describe()) {
+            self.info(&event);
+        }
+        Self {
+            fault_injector,
+            ..self
+        }
+    }
+
+    pub fn fault_injector(&self) -> Option<&FaultInjector> {
+        self.fault_injector.as_ref()
+    }
+
+    pub fn with_script(self, script: Option<ScriptConnection>) -> Self {
+        Self { script, ..self }
+    }
+
+    pub fn script(&self) -> Option<&ScriptConnection> {
+        self.script.as_ref()
+    }
+
+    pub fn direction(&self) -> Direction {
+        if self.upload {
+            Direction::Upload
+        } else {
+            Direction::Download
+        }
+    }
+
+    /// Records bytes written to the other side, with the atom they encode if any.
+    pub fn record(&self, data: &[u8], atom: Option<&UnknownAtom>) {
+        self.offset.fetch_add(data.len() as u64, Ordering::Relaxed);
+        if let Some(capture) = &self.capture {
+            capture.data(self.direction(), data, atom);
+        }
+    }
+
+    fn record_close(&self) {
+        if let Some(capture) = &self.capture {
+            capture.close(self.direction());
         }
     }
 
@@ -31,43 +158,122 @@ impl NDJson {
         self.output_internal("raw", json!(payload));
     }
 
-    pub fn output(&self, atom: &Atom) {
-        self.output_internal("atom", json!(atom));
+    pub fn output(&self, atom: &UnknownAtom) {
+        let Some(atom) = self.filter.apply(atom) else {
+            return;
+        };
+        if let Some(rate_limiter) = &self.rate_limiter {
+            match rate_limiter.check(&atom, Instant::now()) {
+                None => return,
+                Some(0) => {}
+                Some(suppressed) => {
+                    self.info_suppressed(&atom.to_identifier_string(), suppressed);
+                }
+            }
+        }
+        let atom_json = AtomJson::new(&atom).with_data_preview(self.filter.data_preview);
+        self.output_internal("atom", json!(atom_json));
+    }
+
+    fn info_suppressed(&self, identifier: &str, suppressed: u64) {
+        self.info(&format!(
+            "Filter: Suppressed {} {} atoms",
+            suppressed, identifier
+        ));
+    }
+
+    fn flush_suppressed(&self) {
+        let Some(rate_limiter) = &self.rate_limiter else {
+            return;
+        };
+        for (identifier, suppressed) in rate_limiter.take_suppressed() {
+            self.info_suppressed(&identifier, suppressed);
+        }
     }
 
     pub fn info(&self, payload: &str) {
         self.output_internal("info", json!(payload));
     }
 
+    pub fn error(&self, context: &str, err: &anyhow::Error) {
+        self.output_internal("error", error_payload(context, err));
+    }
+
     pub fn disconnected_by_client(self, error_kind: Option<ErrorKind>) {
-        let msg: Cow<'static, str> = if let Some(error_kind) = error_kind {
-            format!("disconnected by client ({})", error_kind).into()
-        } else {
-            "disconnected by client".into()
-        };
-        self.output_internal("info", json!(&msg));
+        self.closed("client", error_kind);
     }
 
     pub fn disconnected_by_server(self, error_kind: Option<ErrorKind>) {
-        let msg: Cow<'static, str> = if let Some(error_kind) = error_kind {
-            format!("disconnected by server ({})", error_kind).into()
-        } else {
-            "disconnected by server".into()
-        };
-        self.output_internal("info", json!(&msg));
+        self.closed("server", error_kind);
+    }
+
+    fn closed(self, by: &str, error_kind: Option<ErrorKind>) {
+        self.record_close();
+        self.flush_suppressed();
+        let payload = json!({
+            "by": by,
+            "error": error_kind.map(|x| x.to_string()),
+            "bytes": self.offset.load(Ordering::Relaxed),
+        });
+        self.output_internal("closed", payload);
     }
 
     fn output_internal(&self, type_param: &str, payload: Value) {
         let direction = if self.upload { "upload" } else { "download" };
-        println!(
-            "{}",
-            json!({
-                "clientHost": self.client_host,
-                "serverHost": self.server_host,
-                "direction": direction,
-                "type": type_param,
-                "payload": payload,
-            })
-        );
+        let (time, monotonic_micros) = now();
+        // Holds stdout while numbering so that lines of a connection come out in `seq` order.
+        let mut stdout = std::io::stdout().lock();
+        let seq = self.connection.next_seq.fetch_add(1, Ordering::Relaxed);
+        let line = json!({
+            "schemaVersion": SCHEMA_VERSION,
+            "connectionId": self.connection.id,
+            "seq": seq,
+            "time": time,
+            "monotonicMicros": monotonic_micros,
+            "clientHost": self.connection.client_host,
+            "serverHost": self.connection.server_host,
+            "direction": direction,
+            "offset": self.offset.load(Ordering::Relaxed),
+            "type": type_param,
+            "payload": payload,
+        });
+        // Nothing is left to report to when stdout is closed.
+        let _ = writeln!(stdout, "{}", line);
     }
 }
+
+/// `time` and `monotonicMicros` of an event
+fn now() -> (u64, u64) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_millis() as u64);
+    (time, STARTED_AT.elapsed().as_micros() as u64)
+}
+
+fn error_payload(context: &str, err: &anyhow::Error) -> Value {
+    json!({ "context": context, "message": format!("{:#}", err) })
+}
+
+/// Outputs an `error` event outside a connection.
+pub fn output_error(
+    client_host: Option<&str>,
+    server_host: Option<&str>,
+    context: &str,
+    err: &anyhow::Error,
+) {
+    let (time, monotonic_micros) = now();
+    let line = json!({
+        "schemaVersion": SCHEMA_VERSION,
+        "connectionId": null,
+        "seq": null,
+        "time": time,
+        "monotonicMicros": monotonic_micros,
+        "clientHost": client_host,
+        "serverHost": server_host,
+        "direction": null,
+        "offset": null,
+        "type": "error",
+        "payload": error_payload(context, err),
+    });
+    let _ = writeln!(std::io::stdout().lock(), "{}", line);
+}
@@ -0,0 +1,368 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+
+use super::capture::Direction;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+const LINKTYPE_ETHERNET: u16 = 1;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+/// Payload per frame, as on an Ethernet link.
+const MAX_SEGMENT_SIZE: usize = 1460;
+const CLIENT_ISN: u32 = 1_000_000;
+const SERVER_ISN: u32 = 2_000_000;
+const CLIENT_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+const SERVER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+
+fn block(r#type: u32, body: &[u8]) -> Vec<u8> {
+    let padding = (4 - body.len() % 4) % 4;
+    let total_len = (12 + body.len() + padding) as u32;
+    let mut buf = Vec::with_capacity(total_len as usize);
+    buf.extend(r#type.to_le_bytes());
+    buf.extend(total_len.to_le_bytes());
+    buf.extend(body);
+    buf.extend(std::iter::repeat_n(0, padding));
+    buf.extend(total_len.to_le_bytes());
+    buf
+}
+
+fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for chunk in chunks {
+        for pair in chunk.chunks(2) {
+            sum += u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Puts both ends in the same address family, mapping IPv4 into IPv6 if they differ.
+fn same_family(src: SocketAddr, dst: SocketAddr) -> (SocketAddr, SocketAddr) {
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => (src, dst),
+        _ => {
+            let to_v6 = |addr: SocketAddr| match addr.ip() {
+                IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+                IpAddr::V6(_) => addr,
+            };
+            (to_v6(src), to_v6(dst))
+        }
+    }
+}
+
+/// An Ethernet frame carrying a TCP segment from `src` to `dst`.
+fn tcp_frame(
+    src: SocketAddr,
+    dst: SocketAddr,
+    (src_mac, dst_mac): ([u8; 6], [u8; 6]),
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let (src, dst) = same_family(src, dst);
+    let tcp_len = 20 + payload.len();
+    let mut tcp = Vec::with_capacity(tcp_len);
+    tcp.extend(src.port().to_be_bytes());
+    tcp.extend(dst.port().to_be_bytes());
+    tcp.extend(seq.to_be_bytes());
+    tcp.extend(ack.to_be_bytes());
+    tcp.push(5 << 4); // data offset
+    tcp.push(flags);
+    tcp.extend(u16::MAX.to_be_bytes()); // window
+    tcp.extend([0, 0]); // checksum
+    tcp.extend([0, 0]); // urgent pointer
+    tcp.extend(payload);
+
+    let mut frame = Vec::with_capacity(14 + 40 + tcp_len);
+    frame.extend(dst_mac);
+    frame.extend(src_mac);
+    let pseudo_header = match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            frame.extend(0x0800u16.to_be_bytes());
+            let mut ip = Vec::with_capacity(20);
+            ip.push(0x45);
+            ip.push(0);
+            ip.extend(((20 + tcp_len) as u16).to_be_bytes());
+            ip.extend([0, 0, 0x40, 0]); // identification, don't fragment
+            ip.push(64); // ttl
+            ip.push(6); // tcp
+            ip.extend([0, 0]);
+            ip.extend(src_ip.octets());
+            ip.extend(dst_ip.octets());
+            let sum = checksum(&[&ip]);
+            ip[10..12].copy_from_slice(&sum.to_be_bytes());
+            frame.extend(&ip);
+            [
+                &src_ip.octets()[..],
+                &dst_ip.octets(),
+                &[0, 6],
+                &(tcp_len as u16).to_be_bytes(),
+            ]
+            .concat()
+        }
+        (IpAddr::V6(src_ip), IpAddr::V6(dst_ip)) => {
+            frame.extend(0x86DDu16.to_be_bytes());
+            frame.extend([0x60, 0, 0, 0]);
+            frame.extend((tcp_len as u16).to_be_bytes());
+            frame.push(6); // next header: tcp
+            frame.push(64); // hop limit
+            frame.extend(src_ip.octets());
+            frame.extend(dst_ip.octets());
+            [
+                &src_ip.octets()[..],
+                &dst_ip.octets(),
+                &(tcp_len as u32).to_be_bytes(),
+                &[0, 0, 0, 6],
+            ]
+            .concat()
+        }
+        _ => unreachable!(),
+    };
+    let sum = checksum(&[&pseudo_header, &tcp]);
+    tcp[16..18].copy_from_slice(&sum.to_be_bytes());
+    frame.extend(tcp);
+    frame
+}
+
+/// Writes proxied connections as a pcapng file which Wireshark can open.
+pub struct PcapngWriter {
+    file: Mutex<BufWriter<File>>,
+}
+
+impl PcapngWriter {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let mut section_header = Vec::new();
+        section_header.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        section_header.extend(1u16.to_le_bytes()); // major version
+        section_header.extend(0u16.to_le_bytes()); // minor version
+        section_header.extend((-1i64).to_le_bytes()); // section length unknown
+        file.write_all(&block(SECTION_HEADER_BLOCK, &section_header))?;
+        let mut interface = Vec::new();
+        interface.extend(LINKTYPE_ETHERNET.to_le_bytes());
+        interface.extend(0u16.to_le_bytes()); // reserved
+        interface.extend(0u32.to_le_bytes()); // no snap length
+        file.write_all(&block(INTERFACE_DESCRIPTION_BLOCK, &interface))?;
+        file.flush()?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    fn write_frame(&self, frame: &[u8]) {
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let mut body = Vec::with_capacity(20 + frame.len());
+        body.extend(0u32.to_le_bytes()); // interface id
+        body.extend(((micros >> 32) as u32).to_le_bytes());
+        body.extend((micros as u32).to_le_bytes());
+        body.extend((frame.len() as u32).to_le_bytes());
+        body.extend((frame.len() as u32).to_le_bytes());
+        body.extend(frame);
+        let mut file = self.file.lock().unwrap();
+        let result = file
+            .write_all(&block(ENHANCED_PACKET_BLOCK, &body))
+            .and_then(|_| file.flush());
+        if let Err(err) = result {
+            log::error!("pcapng: {}", err);
+        }
+    }
+
+    pub fn connection(
+        self: &Arc<Self>,
+        client: SocketAddr,
+        server: SocketAddr,
+    ) -> PcapngConnection {
+        PcapngConnection {
+            writer: self.clone(),
+            client,
+            server,
+            state: Mutex::new(TcpState {
+                opened: false,
+                client_seq: CLIENT_ISN + 1,
+                server_seq: SERVER_ISN + 1,
+            }),
+        }
+    }
+}
+
+struct TcpState {
+    opened: bool,
+    /// Next sequence number of each side
+    client_seq: u32,
+    server_seq: u32,
+}
+
+/// A proxied connection shown as a single TCP connection between the client and the server.
+pub struct PcapngConnection {
+    writer: Arc<PcapngWriter>,
+    client: SocketAddr,
+    server: SocketAddr,
+    state: Mutex<TcpState>,
+}
+
+impl PcapngConnection {
+    fn write_segment(&self, state: &TcpState, direction: Direction, flags: u8, payload: &[u8]) {
+        let frame = match direction {
+            Direction::Upload => tcp_frame(
+                self.client,
+                self.server,
+                (CLIENT_MAC, SERVER_MAC),
+                state.client_seq,
+                state.server_seq,
+                flags,
+                payload,
+            ),
+            Direction::Download => tcp_frame(
+                self.server,
+                self.client,
+                (SERVER_MAC, CLIENT_MAC),
+                state.server_seq,
+                state.client_seq,
+                flags,
+                payload,
+            ),
+        };
+        self.writer.write_frame(&frame);
+    }
+
+    /// The three-way handshake before the first segment.
+    fn open(&self, state: &mut TcpState) {
+        if state.opened {
+            return;
+        }
+        state.opened = true;
+        let syn = tcp_frame(
+            self.client,
+            self.server,
+            (CLIENT_MAC, SERVER_MAC),
+            CLIENT_ISN,
+            0,
+            TCP_SYN,
+            &[],
+        );
+        self.writer.write_frame(&syn);
+        let syn_ack = tcp_frame(
+            self.server,
+            self.client,
+            (SERVER_MAC, CLIENT_MAC),
+            SERVER_ISN,
+            CLIENT_ISN + 1,
+            TCP_SYN | TCP_ACK,
+            &[],
+        );
+        self.writer.write_frame(&syn_ack);
+        self.write_segment(state, Direction::Upload, TCP_ACK, &[]);
+    }
+
+    fn advance(state: &mut TcpState, direction: Direction, len: u32) {
+        let seq = match direction {
+            Direction::Upload => &mut state.client_seq,
+            Direction::Download => &mut state.server_seq,
+        };
+        *seq = seq.wrapping_add(len);
+    }
+
+    pub fn data(&self, direction: Direction, data: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        self.open(&mut state);
+        for segment in data.chunks(MAX_SEGMENT_SIZE) {
+            self.write_segment(&state, direction, TCP_PSH | TCP_ACK, segment);
+            Self::advance(&mut state, direction, segment.len() as u32);
+        }
+    }
+
+    /// `FIN` from the side which closed, acknowledged by the other.
+    pub fn close(&self, direction: Direction) {
+        let mut state = self.state.lock().unwrap();
+        self.open(&mut state);
+        self.write_segment(&state, direction, TCP_FIN | TCP_ACK, &[]);
+        Self::advance(&mut state, direction, 1);
+        let other = match direction {
+            Direction::Upload => Direction::Download,
+            Direction::Download => Direction::Upload,
+        };
+        self.write_segment(&state, other, TCP_ACK, &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use crate::features::capture::Direction;
+
+    use super::{
+        checksum, PcapngWriter, CLIENT_ISN, ENHANCED_PACKET_BLOCK, SECTION_HEADER_BLOCK, SERVER_ISN,
+    };
+
+    fn u32_at(buf: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_pcapng() {
+        let path = std::env::temp_dir().join(format!("pcpproxy-pcapng-{}", std::process::id()));
+        let writer = Arc::new(PcapngWriter::create(&path).unwrap());
+        let client: SocketAddr = "192.168.0.2:50000".parse().unwrap();
+        let server: SocketAddr = "192.168.0.1:7144".parse().unwrap();
+        let connection = writer.connection(client, server);
+        connection.data(Direction::Upload, b"pcp\n");
+        connection.data(Direction::Download, &[0; 3000]);
+        connection.close(Direction::Download);
+        drop((connection, writer));
+        let buf = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(u32_at(&buf, 0), SECTION_HEADER_BLOCK);
+        let mut frames = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let len = u32_at(&buf, pos + 4) as usize;
+            assert_eq!(u32_at(&buf, pos + len - 4) as usize, len);
+            if u32_at(&buf, pos) == ENHANCED_PACKET_BLOCK {
+                let captured = u32_at(&buf, pos + 20) as usize;
+                frames.push(buf[pos + 28..pos + 28 + captured].to_vec());
+            }
+            pos += len;
+        }
+        // SYN, SYN/ACK, ACK, 1 upload, 3 download, FIN, ACK
+        assert_eq!(frames.len(), 9);
+        let upload = &frames[3];
+        let ip = &upload[14..34];
+        assert_eq!(checksum(&[ip]), 0);
+        let tcp = &upload[34..];
+        let pseudo_header = [&ip[12..20], &[0, 6], &(tcp.len() as u16).to_be_bytes()].concat();
+        assert_eq!(checksum(&[&pseudo_header, tcp]), 0);
+        assert_eq!(
+            u32::from_be_bytes(tcp[4..8].try_into().unwrap()),
+            CLIENT_ISN + 1
+        );
+        assert_eq!(&tcp[20..], b"pcp\n");
+        let fin = &frames[7][34..];
+        let seq = u32::from_be_bytes(fin[4..8].try_into().unwrap());
+        let ack = u32::from_be_bytes(fin[8..12].try_into().unwrap());
+        assert_eq!((seq, ack), (SERVER_ISN + 1 + 3000, CLIENT_ISN + 1 + 4));
+        assert_eq!(fin[13], 0x11);
+    }
+}
@@ -1,3 +0,0 @@
-pub mod atom;
-pub mod atom_identifier;
-pub mod atom_stream;
@@ -1,58 +0,0 @@
-#![allow(dead_code)]
-
-pub const AGNT: &[u8; 4] = b"agnt";
-pub const ALBM: &[u8; 4] = b"albm";
-pub const BCID: &[u8; 4] = b"bcid";
-pub const BCST: &[u8; 4] = b"bcst";
-pub const BITR: &[u8; 4] = b"bitr";
-pub const CHAN: &[u8; 4] = b"chan";
-pub const CID: &[u8; 4] = b"cid\0";
-pub const CMNT: &[u8; 4] = b"cmnt";
-pub const CONT: &[u8; 4] = b"cont";
-pub const CREA: &[u8; 4] = b"crea";
-pub const DATA: &[u8; 4] = b"data";
-pub const DESC: &[u8; 4] = b"desc";
-pub const DEST: &[u8; 4] = b"dest";
-pub const FLG1: &[u8; 4] = b"flg1";
-pub const FROM: &[u8; 4] = b"from";
-pub const GNRE: &[u8; 4] = b"gnre";
-pub const GRP: &[u8; 4] = b"grp\0";
-pub const HELO: &[u8; 4] = b"helo";
-pub const HOPS: &[u8; 4] = b"hops";
-pub const HOST: &[u8; 4] = b"host";
-pub const ID: &[u8; 4] = b"id\0\0";
-pub const INFO: &[u8; 4] = b"info";
-pub const IP: &[u8; 4] = b"ip\0\0";
-pub const NAME: &[u8; 4] = b"name";
-pub const NEWP: &[u8; 4] = b"newp";
-pub const NUML: &[u8; 4] = b"numl";
-pub const NUMR: &[u8; 4] = b"numr";
-pub const OK: &[u8; 4] = b"ok\0\0";
-pub const OLDP: &[u8; 4] = b"oldp";
-pub const OLEH: &[u8; 4] = b"oleh";
-pub const PCP: &[u8; 4] = b"pcp\n";
-pub const PKT: &[u8; 4] = b"pkt\0";
-pub const PORT: &[u8; 4] = b"port";
-pub const POS: &[u8; 4] = b"pos\0";
-pub const PUSH: &[u8; 4] = b"push";
-pub const QUIT: &[u8; 4] = b"quit";
-pub const RIP: &[u8; 4] = b"rip\0";
-pub const ROOT: &[u8; 4] = b"root";
-pub const SEXT: &[u8; 4] = b"sext";
-pub const SID: &[u8; 4] = b"sid\0";
-pub const STYP: &[u8; 4] = b"styp";
-pub const TITL: &[u8; 4] = b"titl";
-pub const TRCK: &[u8; 4] = b"trck";
-pub const TTL: &[u8; 4] = b"ttl\0";
-pub const TYPE: &[u8; 4] = b"type";
-pub const UPHP: &[u8; 4] = b"uphp";
-pub const UPIP: &[u8; 4] = b"upip";
-pub const UPPT: &[u8; 4] = b"uppt";
-pub const UPTM: &[u8; 4] = b"uptm";
-pub const URL: &[u8; 4] = b"url\0";
-pub const VER: &[u8; 4] = b"ver\0";
-pub const VERS: &[u8; 4] = b"vers";
-pub const VEVP: &[u8; 4] = b"vevp";
-pub const VEXN: &[u8; 4] = b"vexn";
-pub const VEXP: &[u8; 4] = b"vexp";
-pub const VRVP: &[u8; 4] = b"vrvp";
@@ -1,6 +1,8 @@
 use std::net::{IpAddr, SocketAddr};
 use std::{num::NonZeroU16, time::Duration};
 
+use anyhow::{Context, Result};
+use peercastoxide_lib::net::canonical;
 use regex::Regex;
 use tokio::io::BufReader;
 use tokio::net::TcpStream;
@@ -8,10 +10,12 @@ use tokio::{net::TcpListener, spawn, time::timeout};
 
 use crate::core::http_proxy::proxy_http::pipe_request_header;
 use crate::core::http_proxy::proxy_http::pipe_response_header;
+use crate::core::options::ProxyOptions;
 use crate::core::pcp_proxy::pipe::pipe_pcp;
 use crate::core::utils::disconnect_conn_of_download;
 use crate::core::utils::disconnect_conn_of_upload;
-use crate::features::output::ndjson::NDJson;
+use crate::features::fault::ConnectionType;
+use crate::features::output::ndjson::output_error;
 
 async fn on_connect(
     client: TcpStream,
@@ -19,79 +23,86 @@ async fn on_connect(
     ip_addr_from_real_server: IpAddr,
     listen_port: NonZeroU16,
     tip_host: SocketAddr,
-) -> anyhow::Result<()> {
-    let client_addr = client.peer_addr().unwrap();
+    options: ProxyOptions,
+) -> Result<()> {
+    let client_addr = canonical(client.peer_addr()?);
 
     let (client_incoming, mut client_outgoing) = client.into_split();
-    let server = TcpStream::connect(&tip_host).await?;
+    let server = TcpStream::connect(&tip_host)
+        .await
+        .with_context(|| format!("connecting to {}", tip_host))?;
+    let capture = options
+        .recorder
+        .connection(client_addr, server.peer_addr()?);
     let (server_incoming, mut server_outgoing) = server.into_split();
 
-    {
-        let client_host = client_addr.to_string();
-        spawn(async move {
-            let output = NDJson::upload(client_host, tip_host.to_string());
-            let result = async {
-                let replacement_pair = std::sync::Mutex::new(None);
-                let mut client_incoming = BufReader::new(client_incoming);
-                pipe_request_header(
-                    &mut client_incoming,
-                    &mut server_outgoing,
-                    |mut line| async {
-                        let pattern = r"^Host: ?([^\r\n]+)\r?\n$";
-                        if let Some(capture) = Regex::new(pattern).unwrap().captures(&line) {
-                            let my_host = capture[1].to_owned();
-                            line = line.replace(&my_host, &tip_host.to_string());
-                            *replacement_pair.lock().unwrap() = Some((my_host, tip_host));
-                        }
-                        line
-                    },
-                    &output,
-                )
-                .await?;
-                if let Some((from, to)) = replacement_pair.lock().unwrap().as_ref() {
-                    output.info(&format!("Proxy: Replaced {} with {}", from, to));
-                }
-                pipe_pcp(
-                    client_incoming,
-                    server_outgoing,
-                    real_server_port,
-                    ip_addr_from_real_server,
-                    listen_port,
-                    &output,
-                )
-                .await
-            }
-            .await;
-            disconnect_conn_of_upload(result, output).unwrap();
-        });
-    }
-    {
-        let client_host = client_addr.to_string();
-        spawn(async move {
-            let output = NDJson::download(client_host.clone(), tip_host.to_string());
-            let result = async {
-                let mut server_incoming = BufReader::new(server_incoming);
-                pipe_response_header(
-                    &mut server_incoming,
-                    &mut client_outgoing,
-                    |line| async { line },
-                    &output,
-                )
-                .await?;
-                pipe_pcp(
-                    server_incoming,
-                    client_outgoing,
-                    real_server_port,
-                    ip_addr_from_real_server,
-                    listen_port,
-                    &output,
-                )
-                .await
+    let (upload_output, download_output) = options.outputs(
+        client_addr.to_string(),
+        tip_host.to_string(),
+        capture,
+        ConnectionType::Http,
+    );
+    let options_clone = options.clone();
+    spawn(async move {
+        let result = async {
+            let replacement_pair = std::sync::Mutex::new(None);
+            let mut client_incoming = BufReader::new(client_incoming);
+            pipe_request_header(
+                &mut client_incoming,
+                &mut server_outgoing,
+                |mut line| async {
+                    let pattern = r"^Host: ?([^\r\n]+)\r?\n$";
+                    if let Some(capture) = Regex::new(pattern).unwrap().captures(&line) {
+                        let my_host = capture[1].to_owned();
+                        line = line.replace(&my_host, &tip_host.to_string());
+                        *replacement_pair.lock().unwrap() = Some((my_host, tip_host));
+                    }
+                    line
+                },
+                &upload_output,
+            )
+            .await?;
+            if let Some((from, to)) = replacement_pair.lock().unwrap().as_ref() {
+                upload_output.info(&format!("Proxy: Replaced {} with {}", from, to));
             }
-            .await;
-            disconnect_conn_of_download(result, output).unwrap();
-        });
-    }
+            pipe_pcp(
+                client_incoming,
+                server_outgoing,
+                real_server_port,
+                ip_addr_from_real_server,
+                listen_port,
+                &upload_output,
+                &options_clone,
+            )
+            .await
+        }
+        .await;
+        disconnect_conn_of_upload(result, upload_output);
+    });
+    spawn(async move {
+        let result = async {
+            let mut server_incoming = BufReader::new(server_incoming);
+            pipe_response_header(
+                &mut server_incoming,
+                &mut client_outgoing,
+                |line| async { line },
+                &download_output,
+            )
+            .await?;
+            pipe_pcp(
+                server_incoming,
+                client_outgoing,
+                real_server_port,
+                ip_addr_from_real_server,
+                listen_port,
+                &download_output,
+                &options,
+            )
+            .await
+        }
+        .await;
+        disconnect_conn_of_download(result, download_output);
+    });
     Ok(())
 }
 
@@ -101,22 +112,34 @@ fn spawn_listener(
     ip_addr_from_real_server: IpAddr,
     listen_port: NonZeroU16,
     tip_host: SocketAddr,
+    options: ProxyOptions,
 ) {
     spawn(async move {
         let result = match timeout(Duration::from_secs(10), server.accept()).await {
             Ok(ok) => ok,
             Err(_) => return,
         };
-        let (client, _) = result.unwrap();
-        on_connect(
+        let tip_host_string = tip_host.to_string();
+        let (client, client_addr) = match result {
+            Ok(ok) => ok,
+            Err(err) => {
+                output_error(None, Some(&tip_host_string), "accept", &err.into());
+                return;
+            }
+        };
+        let result = on_connect(
             client,
             real_server_port,
             ip_addr_from_real_server,
             listen_port,
             tip_host,
+            options,
         )
-        .await
-        .unwrap();
+        .await;
+        if let Err(err) = result {
+            let client_host = client_addr.to_string();
+            output_error(Some(&client_host), Some(&tip_host_string), "connect", &err);
+        }
     });
 }
 
@@ -125,17 +148,88 @@ pub async fn listen_for(
     ip_addr_from_real_server: IpAddr,
     listen_port: NonZeroU16,
     tip_host: SocketAddr,
-) -> NonZeroU16 {
+    options: ProxyOptions,
+) -> Result<NonZeroU16> {
     let server = TcpListener::bind(SocketAddr::new(ip_addr_from_real_server, 0))
         .await
-        .unwrap();
-    let port = server.local_addr().unwrap().port().try_into().unwrap();
+        .with_context(|| format!("listening for {} on {}", tip_host, ip_addr_from_real_server))?;
+    let port = server.local_addr()?.port().try_into()?;
     spawn_listener(
         server,
         real_server_port,
         ip_addr_from_real_server,
         listen_port,
         tip_host,
+        options,
     );
-    port
+    Ok(port)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{num::NonZeroU16, sync::Arc};
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::{
+        core::options::ProxyOptions,
+        features::{capture::Recorder, pcapng::PcapngWriter},
+    };
+
+    use super::listen_for;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_tip_connection_in_pcapng() {
+        let path = std::env::temp_dir().join(format!("pcpproxy-tip-{}", std::process::id()));
+        let options = ProxyOptions {
+            recorder: Recorder {
+                capture: None,
+                pcapng: Some(Arc::new(PcapngWriter::create(&path).unwrap())),
+            },
+            ..Default::default()
+        };
+        let tip = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tip_addr = tip.local_addr().unwrap();
+        let port = listen_for(
+            NonZeroU16::new(7144).unwrap(),
+            "127.0.0.1".parse().unwrap(),
+            NonZeroU16::new(8144).unwrap(),
+            tip_addr,
+            options,
+        )
+        .await
+        .unwrap();
+
+        let mut client = TcpStream::connect(("127.0.0.1", port.get())).await.unwrap();
+        let request = format!(
+            "GET /channel/0123456789abcdef0123456789abcdef HTTP/1.0\r\nHost: 127.0.0.1:{}\r\n\r\n",
+            port
+        );
+        client.write_all(request.as_bytes()).await.unwrap();
+        let (server, _) = tip.accept().await.unwrap();
+        let mut server = BufReader::new(server);
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            server.read_line(&mut line).await.unwrap();
+        }
+        server
+            .write_all(b"HTTP/1.0 404 Not Found\r\n\r\n")
+            .await
+            .unwrap();
+        drop(server);
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, b"HTTP/1.0 404 Not Found\r\n\r\n");
+
+        let buf = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let contains = |x: &[u8]| buf.windows(x.len()).any(|window| window == x);
+        let host = format!("Host: {}\r\n", tip_addr);
+        assert!(contains(host.as_bytes()));
+        assert!(contains(b"HTTP/1.0 404 Not Found\r\n"));
+    }
 }
@@ -0,0 +1,183 @@
+//! Rewrites of atoms declared in a JSON file, applied by the pipe after its own rewrites.
+//!
+//! The file is an array of rules such as:
+//!
+//! ```json
+//! [
+//!   { "path": "helo", "set": "agnt = \"PeerCast/0.1218\";" },
+//!   { "direction": "download", "path": "bcst/host", "when": "numl = u32:0;", "delete": ["flg1"] },
+//!   { "path": "chan/info", "insert": "bitr = u32:5000;" }
+//! ]
+//! ```
+//!
+//! `path` is an identifier path to the parent atoms to rewrite, `when` lists children they must
+//! have with the same values, and atoms are written in the text form of atoms. Of the actions,
+//! `delete` removes the children with the identifiers, `set` replaces the children with the same
+//! identifiers or appends them, and `insert` appends the children.
+
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use peercastoxide_lib::pcp::atom::{text::from_text, AtomParent, UnknownAtom};
+use serde::Deserialize;
+
+use super::{capture::Direction, output::filter::AtomPath};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleJson {
+    direction: Option<Direction>,
+    path: String,
+    when: Option<String>,
+    #[serde(default)]
+    delete: Vec<String>,
+    set: Option<String>,
+    insert: Option<String>,
+}
+
+struct Rule {
+    direction: Option<Direction>,
+    path: AtomPath,
+    when: Vec<UnknownAtom>,
+    delete: Vec<String>,
+    set: Vec<UnknownAtom>,
+    insert: Vec<UnknownAtom>,
+}
+
+fn parse_atoms(text: Option<&str>) -> Result<Vec<UnknownAtom>> {
+    text.map_or(Ok(Vec::new()), from_text)
+}
+
+impl Rule {
+    fn parse(json: RuleJson) -> Result<Self> {
+        if let Some(identifier) = json.delete.iter().find(|x| x.is_empty() || x.len() > 4) {
+            return Err(anyhow!("invalid identifier {:?} in delete", identifier));
+        }
+        Ok(Self {
+            direction: json.direction,
+            path: json.path.parse()?,
+            when: parse_atoms(json.when.as_deref()).map_err(|err| anyhow!("when: {}", err))?,
+            delete: json.delete,
+            set: parse_atoms(json.set.as_deref()).map_err(|err| anyhow!("set: {}", err))?,
+            insert: parse_atoms(json.insert.as_deref())
+                .map_err(|err| anyhow!("insert: {}", err))?,
+        })
+    }
+
+    /// How many atoms under `atom` at `depth` were rewritten.
+    fn apply_at(&self, depth: usize, atom: &mut UnknownAtom) -> usize {
+        if !self.path.matches_at(depth, atom) {
+            return 0;
+        }
+        let UnknownAtom::Parent(parent) = atom else {
+            return 0;
+        };
+        if !self.path.is_last(depth) {
+            return parent
+                .children_mut()
+                .iter_mut()
+                .map(|x| self.apply_at(depth + 1, x))
+                .sum();
+        }
+        if !self.when.iter().all(|x| parent.children().contains(x)) {
+            return 0;
+        }
+        self.rewrite(parent);
+        1
+    }
+
+    fn rewrite(&self, parent: &mut AtomParent) {
+        let children = parent.children_mut();
+        children.retain(|x| !self.delete.contains(&x.to_identifier_string()));
+        for atom in &self.set {
+            let mut found = false;
+            for child in children
+                .iter_mut()
+                .filter(|x| x.identifier() == atom.identifier())
+            {
+                *child = atom.clone();
+                found = true;
+            }
+            if !found {
+                children.push(atom.clone());
+            }
+        }
+        children.extend(self.insert.iter().cloned());
+    }
+}
+
+#[derive(Default)]
+pub struct RewriteRules(Vec<Rule>);
+
+impl FromStr for RewriteRules {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rules: Vec<RuleJson> = serde_json::from_str(s)?;
+        rules
+            .into_iter()
+            .enumerate()
+            .map(|(i, json)| Rule::parse(json).map_err(|err| anyhow!("rule {}: {}", i + 1, err)))
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+impl RewriteRules {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Numbers of the rules that rewrote `atom` and how many atoms each rewrote.
+    pub fn apply(&self, direction: Direction, atom: &mut UnknownAtom) -> Vec<(usize, usize)> {
+        self.0
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.direction.is_none_or(|x| x == direction))
+            .map(|(i, rule)| (i + 1, rule.apply_at(0, atom)))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use peercastoxide_lib::pcp::atom::text::from_text;
+
+    use crate::features::capture::Direction;
+
+    use super::RewriteRules;
+
+    #[test]
+    fn test_rewrite() {
+        let rules: RewriteRules = r#"[
+            { "path": "helo", "set": "agnt = \"Fake\"; ver = u32:1;", "delete": ["port"] },
+            { "direction": "upload", "path": "bcst/host", "when": "numl = u32:0;",
+              "insert": "numl = u32:9;" }
+        ]"#
+        .parse()
+        .unwrap();
+
+        let mut helo = from_text(r#"helo { agnt = "Real"; port = u16:7144; }"#).unwrap()[0].clone();
+        assert_eq!(rules.apply(Direction::Download, &mut helo), [(1, 1)]);
+        assert_eq!(
+            helo,
+            from_text(r#"helo { agnt = "Fake"; ver = u32:1; }"#).unwrap()[0]
+        );
+
+        let text = "bcst { host { numl = u32:0; } host { numl = u32:1; } host { numl = u32:0; } }";
+        let mut bcst = from_text(text).unwrap()[0].clone();
+        assert!(rules.apply(Direction::Download, &mut bcst).is_empty());
+        assert_eq!(rules.apply(Direction::Upload, &mut bcst), [(2, 2)]);
+        let text = "bcst { host { numl = u32:0; numl = u32:9; } host { numl = u32:1; } \
+                    host { numl = u32:0; numl = u32:9; } }";
+        assert_eq!(bcst, from_text(text).unwrap()[0]);
+
+        let err =
+            r#"[{ "path": "helo", "delete": ["toolong"] }]"#.parse::<RewriteRules>().err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "rule 1: invalid identifier \"toolong\" in delete"
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! Atom hooks written in Rhai, run by the pipe after the rewrite rules.
+//!
+//! The script defines `on_atom(atom, direction)`, called with each atom read from a peer and
+//! `"upload"` or `"download"`. An atom is a map: `#{ id: "helo", children: [...] }` for a parent
+//! and `#{ id: "port", data: blob }` for a child. The function returns the atom to pass on, an
+//! array of atoms to pass on instead (empty to drop it), or `()` to pass it on unchanged. `this` is
+//! a map kept for the connection across both directions.
+//!
+//! ```rhai
+//! fn on_atom(atom, direction) {
+//!     this.count = (this.count ?? 0) + 1;
+//!     if atom.id == "helo" {
+//!         atom.children.push(from_text("agnt = \"Fake\";")[0]);
+//!         return atom;
+//!     }
+//! }
+//! ```
+//!
+//! `to_text(atom)` and `from_text(text)` convert between atoms and the text form of atoms, and
+//! `print` writes an `info` line. A call which runs too many operations or nests calls too deeply
+//! fails like any other script error.
+
+use std::{
+    cell::RefCell,
+    fs,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use peercastoxide_lib::pcp::atom::{
+    text::{from_text, to_text},
+    UnknownAtom,
+};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+
+use super::capture::Direction;
+
+/// Operations a call of `on_atom` may run, so that a stuck loop can't stall the pipe
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// Nesting of function calls, well within the stack of a Tokio worker thread
+const MAX_CALL_LEVELS: usize = 32;
+
+thread_local! {
+    /// What the script printed in the current call
+    static PRINTED: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+fn to_dynamic(atom: &UnknownAtom) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("id".into(), atom.to_identifier_string().into());
+    match atom {
+        UnknownAtom::Parent(parent) => {
+            let children = parent.children().iter().map(to_dynamic).collect::<Array>();
+            map.insert("children".into(), children.into());
+        }
+        UnknownAtom::Child(child) => {
+            map.insert("data".into(), Dynamic::from_blob(child.data().to_vec()));
+        }
+    }
+    map.into()
+}
+
+fn from_dynamic(value: Dynamic) -> Result<UnknownAtom> {
+    let type_name = value.type_name();
+    let mut map = value
+        .try_cast::<Map>()
+        .ok_or_else(|| anyhow!("atom must be a map, not {}", type_name))?;
+    let id = map
+        .remove("id")
+        .and_then(|x| x.into_string().ok())
+        .ok_or_else(|| anyhow!("atom has no id string"))?;
+    if id.is_empty() || id.len() > 4 {
+        return Err(anyhow!("invalid id {:?}", id));
+    }
+    let mut identifier = [0; 4];
+    identifier[..id.len()].copy_from_slice(id.as_bytes());
+    if let Some(children) = map.remove("children") {
+        let children = children
+            .into_array()
+            .map_err(|_| anyhow!("children of {} must be an array", id))?
+            .into_iter()
+            .map(from_dynamic)
+            .collect::<Result<_>>()?;
+        return Ok(UnknownAtom::parent(identifier, children));
+    }
+    let data = map
+        .remove("data")
+        .ok_or_else(|| anyhow!("{} has neither children nor data", id))?
+        .into_blob()
+        .map_err(|_| anyhow!("data of {} must be a blob", id))?;
+    Ok(UnknownAtom::child(identifier, data))
+}
+
+fn to_rhai_error(err: anyhow::Error) -> Box<EvalAltResult> {
+    err.to_string().into()
+}
+
+pub struct AtomScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl FromStr for AtomScript {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .on_print(|text| PRINTED.with(|x| x.borrow_mut().push(text.to_owned())))
+            .on_debug(|text, _, _| PRINTED.with(|x| x.borrow_mut().push(text.to_owned())))
+            .register_fn("to_text", |atom: Dynamic| {
+                let atom = from_dynamic(atom).map_err(to_rhai_error)?;
+                Ok::<_, Box<EvalAltResult>>(to_text([&atom]))
+            })
+            .register_fn("from_text", |text: &str| {
+                let atoms = from_text(text).map_err(to_rhai_error)?;
+                Ok::<_, Box<EvalAltResult>>(atoms.iter().map(to_dynamic).collect::<Array>())
+            });
+        let ast = engine.compile(s).map_err(|err| anyhow!("{}", err))?;
+        if !ast
+            .iter_functions()
+            .any(|x| x.name == "on_atom" && x.params.len() == 2)
+        {
+            return Err(anyhow!("the script has no on_atom(atom, direction)"));
+        }
+        Ok(Self { engine, ast })
+    }
+}
+
+impl AtomScript {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Starts the state of a connection. Both of its directions share the returned handle.
+    pub fn connection(self: &Arc<Self>) -> ScriptConnection {
+        ScriptConnection {
+            script: self.clone(),
+            this: Arc::new(Mutex::new(Map::new().into())),
+        }
+    }
+}
+
+/// The result of a hook: the atoms to pass on and what the script printed.
+pub struct HookResult {
+    pub atoms: Result<Vec<UnknownAtom>>,
+    pub printed: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct ScriptConnection {
+    script: Arc<AtomScript>,
+    this: Arc<Mutex<Dynamic>>,
+}
+
+impl ScriptConnection {
+    pub fn on_atom(&self, atom: &UnknownAtom, direction: Direction) -> HookResult {
+        let direction = match direction {
+            Direction::Upload => "upload",
+            Direction::Download => "download",
+        };
+        let mut this = self.this.lock().unwrap();
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut this);
+        let result = self.script.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            &self.script.ast,
+            "on_atom",
+            (to_dynamic(atom), direction),
+        );
+        let atoms = match result {
+            Err(err) => Err(anyhow!("{}", err)),
+            Ok(value) if value.is_unit() => Ok(vec![atom.clone()]),
+            Ok(value) if value.is_array() => value
+                .into_array()
+                .unwrap()
+                .into_iter()
+                .map(from_dynamic)
+                .collect(),
+            Ok(value) => from_dynamic(value).map(|x| vec![x]),
+        };
+        HookResult {
+            atoms,
+            printed: PRINTED.with(|x| x.take()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use peercastoxide_lib::pcp::atom::text::from_text;
+
+    use crate::features::capture::Direction;
+
+    use super::AtomScript;
+
+    #[test]
+    fn test_script() {
+        let script: AtomScript = r#"
+            fn on_atom(atom, direction) {
+                this.count = (this.count ?? 0) + 1;
+                print(`${direction} ${atom.id} ${this.count}`);
+                if atom.id == "bcst" {
+                    return [];
+                }
+                if atom.id == "helo" {
+                    atom.children[0].data = blob(2, 0);
+                    return [atom, from_text("quit = u32:1000;")[0]];
+                }
+            }
+        "#
+        .parse()
+        .unwrap();
+        let script = Arc::new(script);
+
+        let connection = script.connection();
+        let helo = from_text("helo { port = u16:7144; }").unwrap();
+        let result = connection.on_atom(&helo[0], Direction::Upload);
+        let expected = from_text("helo { port = u16:0; } quit = u32:1000;").unwrap();
+        assert_eq!(result.atoms.unwrap(), expected);
+        assert_eq!(result.printed, ["upload helo 1"]);
+
+        let bcst = from_text("bcst { }").unwrap();
+        let result = connection.clone().on_atom(&bcst[0], Direction::Download);
+        assert!(result.atoms.unwrap().is_empty());
+        assert_eq!(result.printed, ["download bcst 2"]);
+
+        let ok = from_text("ok = u32:1;").unwrap();
+        let result = script.connection().on_atom(&ok[0], Direction::Download);
+        assert_eq!(result.atoms.unwrap(), ok);
+        assert_eq!(result.printed, ["download ok 1"]);
+
+        let err = "fn on_atom(atom) {}".parse::<AtomScript>().err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "the script has no on_atom(atom, direction)"
+        );
+    }
+
+    #[test]
+    fn test_script_limits() {
+        let ok = from_text("ok = u32:1;").unwrap();
+        let script: AtomScript = "fn on_atom(atom, direction) { loop {} }".parse().unwrap();
+        let err = Arc::new(script)
+            .connection()
+            .on_atom(&ok[0], Direction::Upload)
+            .atoms
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string().starts_with("Too many operations"),
+            "{}",
+            err
+        );
+
+        let script: AtomScript = r#"
+            fn nest(n) { nest(n + 1) }
+            fn on_atom(atom, direction) { nest(0) }
+        "#
+        .parse()
+        .unwrap();
+        let err = Arc::new(script)
+            .connection()
+            .on_atom(&ok[0], Direction::Upload)
+            .atoms
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Stack overflow"), "{}", err);
+    }
+}
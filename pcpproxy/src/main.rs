@@ -3,16 +3,35 @@
 mod core;
 mod features;
 
-use std::num::NonZeroU16;
+use std::{
+    net::IpAddr,
+    num::{NonZeroU16, NonZeroU32},
+    sync::Arc,
+};
 
 use anyhow::Result;
-use clap::{Arg, Command};
+use clap::{Arg, ArgGroup, Command};
 use tokio::{
     io::{self, AsyncReadExt},
     spawn,
 };
 
-use crate::core::listen::listen;
+use crate::{
+    core::{
+        listen::listen,
+        options::ProxyOptions,
+        replay::{replay_as_server, replay_to_server},
+        utils::port_of,
+    },
+    features::{
+        capture::{read_capture, CaptureWriter, Recorder},
+        fault::FaultConfig,
+        output::filter::{AtomPath, OutputFilter},
+        pcapng::PcapngWriter,
+        rewrite::RewriteRules,
+        script::AtomScript,
+    },
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -26,6 +45,8 @@ async fn main() -> Result<()> {
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
         .arg(
             Arg::new("listen_port")
                 .help("Listen port")
@@ -37,15 +58,128 @@ async fn main() -> Result<()> {
         .arg(
             Arg::new("ip_addr_from_real_server")
                 .help("IP address from real PeerCast")
+                .validator(|arg| arg.parse::<IpAddr>())
                 .required(true),
         )
         .arg(
             Arg::new("real_server_host")
                 .help("Real PeerCast host (hostname:port)")
+                .validator(port_of)
                 .required(true),
         )
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write every piped chunk to a capture file"),
+        )
+        .arg(
+            Arg::new("pcapng")
+                .long("pcapng")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write proxied connections as TCP frames to a pcapng file for Wireshark"),
+        )
+        .arg(
+            Arg::new("include")
+                .long("include")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("PATH")
+                .validator(|arg| arg.parse::<AtomPath>())
+                .help("Output only atoms containing this identifier path (e.g. chan/pkt)"),
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("PATH")
+                .validator(|arg| arg.parse::<AtomPath>())
+                .help("Leave atoms at this identifier path out of the output (e.g. chan/pkt/data)"),
+        )
+        .arg(
+            Arg::new("data_preview")
+                .long("data-preview")
+                .takes_value(true)
+                .value_name("BYTES")
+                .validator(|arg| arg.parse::<usize>())
+                .help("Output the first bytes of data payloads as hex"),
+        )
+        .arg(
+            Arg::new("rate_limit")
+                .long("rate-limit")
+                .takes_value(true)
+                .value_name("COUNT")
+                .validator(|arg| arg.parse::<NonZeroU32>())
+                .help("Output at most this many atoms of the same shape per second per connection"),
+        )
+        .arg(
+            Arg::new("rules")
+                .long("rules")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Rewrite atoms by the rules in a JSON file"),
+        )
+        .arg(
+            Arg::new("faults")
+                .long("faults")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Inject the faults in a JSON file into proxied streams"),
+        )
+        .arg(
+            Arg::new("script")
+                .long("script")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Pass atoms through on_atom(atom, direction) in a Rhai script"),
+        )
+        .subcommand(
+            Command::new("replay")
+                .about("Play a capture file against a real server or as a fake server")
+                .arg(Arg::new("capture").help("Capture file").required(true))
+                .arg(
+                    Arg::new("server")
+                        .long("server")
+                        .takes_value(true)
+                        .value_name("HOST:PORT")
+                        .help("Send the client side of the capture to this server"),
+                )
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .value_name("PORT")
+                        .validator(|arg| {
+                            NonZeroU16::new(arg.parse()?).ok_or_else(|| anyhow::anyhow!("Zero"))
+                        })
+                        .help("Send the server side of the capture to clients connecting here"),
+                )
+                .group(
+                    ArgGroup::new("target")
+                        .args(&["server", "listen"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("timing")
+                        .long("timing")
+                        .help("Keep the intervals between chunks as captured"),
+                ),
+        )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("replay") {
+        let records = read_capture(matches.value_of("capture").unwrap())?;
+        let timing = matches.is_present("timing");
+        if let Some(server_host) = matches.value_of("server") {
+            return replay_to_server(records, server_host, timing).await;
+        }
+        let listen_port = matches.value_of("listen").unwrap().parse()?;
+        return replay_as_server(records, listen_port, timing).await;
+    }
+
     // exit when stdin is closed
     spawn(async {
         let mut buf = [0u8; 1024];
@@ -56,15 +190,60 @@ async fn main() -> Result<()> {
             }
         }
     });
+    let recorder = Recorder {
+        capture: matches
+            .value_of("record")
+            .map(CaptureWriter::create)
+            .transpose()?
+            .map(Arc::new),
+        pcapng: matches
+            .value_of("pcapng")
+            .map(PcapngWriter::create)
+            .transpose()?
+            .map(Arc::new),
+    };
+    let paths = |name| {
+        matches
+            .values_of(name)
+            .into_iter()
+            .flatten()
+            .map(|x| x.parse().unwrap())
+            .collect()
+    };
+    let filter = OutputFilter {
+        include: paths("include"),
+        exclude: paths("exclude"),
+        data_preview: matches
+            .value_of("data_preview")
+            .map_or(0, |x| x.parse().unwrap()),
+        rate_limit: matches.value_of("rate_limit").map(|x| x.parse().unwrap()),
+    };
+    let rules = matches
+        .value_of("rules")
+        .map(RewriteRules::load)
+        .transpose()?
+        .unwrap_or_default();
+    let faults = matches
+        .value_of("faults")
+        .map(FaultConfig::load)
+        .transpose()?
+        .unwrap_or_default();
+    let script = matches
+        .value_of("script")
+        .map(AtomScript::load)
+        .transpose()?
+        .map(Arc::new);
     listen(
         NonZeroU16::new(matches.value_of("listen_port").unwrap().parse().unwrap()).unwrap(),
-        matches
-            .value_of("ip_addr_from_real_server")
-            .unwrap()
-            .parse()
-            .unwrap(),
+        matches.value_of_t("ip_addr_from_real_server")?,
         matches.value_of("real_server_host").unwrap(),
+        ProxyOptions {
+            recorder,
+            filter: Arc::new(filter),
+            rules: Arc::new(rules),
+            faults: Arc::new(faults),
+            script,
+        },
     )
-    .await;
-    Ok(())
+    .await
 }
@@ -50,10 +50,16 @@ impl SubProcess {
             process.kill().await.unwrap();
             handle.abort();
         }
-        let mut child = Command::new("pcpproxy")
+        let mut command = Command::new("pcpproxy");
+        command
             .arg(settings.listen_port().to_string())
             .arg(settings.ip_addr_from_real_server())
-            .arg(settings.real_server_host())
+            .arg(settings.real_server_host());
+        if *settings.is_skip_data_packet() {
+            // Stream data comes at the bitrate and would flood the log.
+            command.args(["--exclude", "chan/pkt/data"]);
+        }
+        let mut child = command
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .kill_on_drop(true)
@@ -51,20 +51,40 @@ function updatedConnections(
       identifier: '#IFO',
       payload: payload.payload as string,
     };
+  } else if (payload.type === 'closed') {
+    const closed = payload.payload as ClosedPayload;
+    atom = {
+      identifier: '#IFO',
+      payload:
+        `disconnected by ${closed.by}` +
+        (closed.error != null ? ` (${closed.error})` : '') +
+        ` after ${closed.bytes} bytes`,
+    };
+  } else if (payload.type === 'error') {
+    const error = payload.payload as ErrorPayload;
+    atom = {
+      identifier: '#ERR',
+      payload: `${error.context}: ${error.message}`,
+    };
   } else {
     atom = {
       identifier: '#UNK',
       payload: payload.type,
     };
   }
-  const key = `${payload.clientHost}_${payload.serverHost}`;
+  // An error outside a connection is shown as a connection of its own.
+  const key =
+    payload.connectionId ?? `error-${payload.time}-${payload.monotonicMicros}`;
   let connection = connections[key] ?? {
-    clientHost: payload.clientHost,
-    serverHost: payload.serverHost,
+    clientHost: payload.clientHost ?? '',
+    serverHost: payload.serverHost ?? '',
     uploadStream: [],
     downloadStream: [],
   };
-  switch (payload.direction) {
+  if (payload.type === 'connected') {
+    return { ...connections, [key]: connection };
+  }
+  switch (payload.direction ?? 'upload') {
     case 'upload':
       connection = {
         ...connection,
@@ -93,19 +113,40 @@ function updatedConnections(
   return { ...connections, [key]: connection };
 }
 
+export interface ClosedPayload {
+  by: 'client' | 'server';
+  error: string | null;
+  bytes: number;
+}
+
+export interface ErrorPayload {
+  context: string;
+  message: string;
+}
+
+/**
+ * A line of the pcpproxy output, schema version 1. `error` lines outside a connection have `null`
+ * for the connection fields.
+ */
 export interface JsonPayload {
+  schemaVersion: number;
+  connectionId: string | null;
+  seq: number | null;
+  time: number;
+  monotonicMicros: number;
   type: string;
-  clientHost: string;
-  serverHost: string;
-  direction: string;
-  payload: AtomOrRaw | null;
+  clientHost: string | null;
+  serverHost: string | null;
+  direction: string | null;
+  offset: number | null;
+  payload: AtomOrRaw | ClosedPayload | ErrorPayload | null;
 }
 
 export default function App(): JSX.Element {
   const [showSettings, setShowSettings] = useState(false);
   const [settings, setSettings] = useState<Settings | null>(null);
   const [connections, setConnections] = useState<{
-    [clientHostServerHost: string]: {
+    [connectionId: string]: {
       clientHost: string;
       serverHost: string;
       uploadStream: readonly Atom[];
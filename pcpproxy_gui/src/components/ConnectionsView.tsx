@@ -17,7 +17,7 @@ export interface Connection {
 }
 
 export type Connections = {
-  [clientHostServerHost: string]: Connection;
+  [connectionId: string]: Connection;
 };
 
 function Identifier(props: { identifier: string }): JSX.Element {
@@ -2,17 +2,29 @@ import { JsonPayload } from '../App';
 
 const dummyData: JsonPayload[] = [
   {
+    schemaVersion: 1,
+    connectionId: 'dummy1',
+    seq: 0,
+    time: 0,
+    monotonicMicros: 0,
     type: 'atom',
     clientHost: 'dummy1',
     serverHost: 'dummy2',
     direction: 'upload',
+    offset: 0,
     payload: { identifier: '#RAW', payload: 'a' },
   },
   {
+    schemaVersion: 1,
+    connectionId: 'dummy1',
+    seq: 1,
+    time: 0,
+    monotonicMicros: 0,
     type: 'atom',
     clientHost: 'dummy1',
     serverHost: 'dummy2',
     direction: 'download',
+    offset: 0,
     payload: {
       identifier: 'helo',
       children: [
@@ -30,10 +42,16 @@ const dummyData: JsonPayload[] = [
     },
   },
   {
+    schemaVersion: 1,
+    connectionId: 'dummy1',
+    seq: 2,
+    time: 0,
+    monotonicMicros: 0,
     type: 'atom',
     clientHost: 'dummy1',
     serverHost: 'dummy2',
     direction: 'download',
+    offset: 0,
     payload: {
       identifier: 'helo',
       children: [
@@ -43,10 +61,16 @@ const dummyData: JsonPayload[] = [
     },
   },
   {
+    schemaVersion: 1,
+    connectionId: 'dummy2',
+    seq: 0,
+    time: 0,
+    monotonicMicros: 0,
     type: 'atom',
     clientHost: 'dummy2',
     serverHost: 'dummy1',
     direction: 'download',
+    offset: 0,
 
     payload: {
       identifier: 'helo',
@@ -57,10 +81,16 @@ const dummyData: JsonPayload[] = [
     },
   },
   {
+    schemaVersion: 1,
+    connectionId: 'dummy2',
+    seq: 1,
+    time: 0,
+    monotonicMicros: 0,
     type: 'atom',
     clientHost: 'dummy2',
     serverHost: 'dummy1',
     direction: 'download',
+    offset: 0,
     payload: {
       identifier: 'helo',
       children: [
@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    pcp::atom::{values::Id, well_known_atoms::Host},
+    peercast_xml,
+};
+
+/// A host last reported by a `host` atom.
+#[derive(Clone, Debug)]
+pub struct CachedHost {
+    pub host: Host,
+    /// Distance in hops from us when it was reported
+    pub hops: u8,
+    pub last_seen: Instant,
+}
+
+/// Remembers the hosts of each channel.
+#[derive(Debug)]
+pub struct HostCache {
+    hosts: HashMap<(Id, Id), CachedHost>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl HostCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            hosts: HashMap::new(),
+            capacity,
+            ttl,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Replaces the previous report of the same host on the same channel.
+    pub fn update(&mut self, host: Host, hops: u8) {
+        self.update_at(host, hops, Instant::now());
+    }
+
+    fn update_at(&mut self, host: Host, hops: u8, now: Instant) {
+        self.remove_expired(now);
+        let key = (host.cid.clone(), host.id.clone());
+        if !self.hosts.contains_key(&key) && self.hosts.len() >= self.capacity {
+            let oldest = self
+                .hosts
+                .iter()
+                .min_by_key(|(_, x)| x.last_seen)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.hosts.remove(&oldest);
+            }
+        }
+        self.hosts.insert(
+            key,
+            CachedHost {
+                host,
+                hops,
+                last_seen: now,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, channel_id: &Id, session_id: &Id) -> Option<CachedHost> {
+        self.hosts.remove(&(channel_id.clone(), session_id.clone()))
+    }
+
+    pub fn remove_expired(&mut self, now: Instant) {
+        let ttl = self.ttl;
+        self.hosts
+            .retain(|_, x| now.saturating_duration_since(x.last_seen) < ttl);
+    }
+
+    pub fn hosts(&self) -> impl Iterator<Item = &CachedHost> {
+        self.hosts.values()
+    }
+
+    pub fn hosts_for(&self, channel_id: &Id) -> impl Iterator<Item = &CachedHost> {
+        let channel_id = channel_id.clone();
+        self.hosts().filter(move |x| x.host.cid == channel_id)
+    }
+
+    /// Reachable hosts with a free relay slot, closest and least loaded first.
+    pub fn relay_candidates(&self, channel_id: &Id, max: usize) -> Vec<&CachedHost> {
+        let mut candidates = self
+            .hosts_for(channel_id)
+            .filter(|x| !x.host.is_firewalled() && x.host.flg1.relay())
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|x| (x.hops, x.host.numr, u32::MAX - x.host.uptm));
+        candidates.truncate(max);
+        candidates
+    }
+
+    /// `<host_cache>` of viewxml
+    pub fn to_xml(&self) -> peercast_xml::HostCache {
+        let now = SystemTime::now();
+        let mut hosts = self.hosts().collect::<Vec<_>>();
+        hosts.sort_by_key(|x| std::cmp::Reverse(x.last_seen));
+        let host = hosts
+            .into_iter()
+            .map(|x| peercast_xml::HostCacheEntry {
+                ip: x.host.global(),
+                r#type: if x.host.flg1.tracker() {
+                    "TRACKER"
+                } else {
+                    "SERVENT"
+                }
+                .into(),
+                time: now
+                    .checked_sub(x.last_seen.elapsed())
+                    .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+                    .map(|x| x.as_secs())
+                    .unwrap_or_default(),
+            })
+            .collect();
+        peercast_xml::HostCache { host }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        time::{Duration, Instant},
+    };
+
+    use crate::pcp::atom::{
+        values::{Flg1, Id, VExP},
+        well_known_atoms::Host,
+    };
+
+    use super::HostCache;
+
+    fn host(id: u8, port: u16, numr: u32, flg1: u8) -> Host {
+        Host {
+            cid: Id([1; 16]),
+            id: Id([id; 16]),
+            ip_port: vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, id)), port).into()],
+            numl: 0,
+            numr,
+            uptm: 0,
+            ver: 1218,
+            vevp: 27,
+            vexp: VExP(*b"OX"),
+            vexn: 1,
+            flg1: Flg1(flg1),
+            oldp: None,
+            newp: None,
+            upip: None,
+            uppt: None,
+            uphp: None,
+        }
+    }
+
+    #[test]
+    fn test_host_cache() {
+        let now = Instant::now();
+        let mut cache = HostCache::new(3, Duration::from_secs(60));
+        cache.update_at(host(1, 7144, 0, 0b10), 3, now);
+        cache.update_at(host(2, 7144, 2, 0b10), 1, now);
+        cache.update_at(host(3, 0, 0, 0b10), 1, now); // firewalled
+        cache.update_at(host(2, 7144, 1, 0b10), 1, now); // replaces
+        assert_eq!(cache.len(), 3);
+        let ids = |cache: &HostCache| {
+            cache
+                .relay_candidates(&Id([1; 16]), 8)
+                .into_iter()
+                .map(|x| x.host.id.0[0])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&cache), [2, 1]);
+        assert!(cache.relay_candidates(&Id([2; 16]), 8).is_empty());
+
+        // full, so the oldest one is evicted
+        cache.update_at(host(4, 7144, 0, 0b10), 1, now + Duration::from_secs(1));
+        assert_eq!(cache.len(), 3);
+        // host 4 is the only one seen within the ttl
+        cache.remove_expired(now + Duration::from_secs(60));
+        assert_eq!(ids(&cache), [4]);
+        assert_eq!(cache.to_xml().host.len(), 1);
+    }
+}
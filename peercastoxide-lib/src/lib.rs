@@ -1,2 +1,6 @@
+pub mod host_cache;
+pub mod media;
+pub mod net;
 pub mod pcp;
 pub mod peercast_xml;
+pub mod playlist;
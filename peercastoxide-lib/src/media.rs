@@ -0,0 +1,2 @@
+pub mod flv;
+pub mod mkv;
@@ -0,0 +1,456 @@
+use anyhow::{bail, Result};
+
+const HEADER_SIZE: usize = 9;
+const PREVIOUS_TAG_SIZE_SIZE: usize = 4;
+const TAG_HEADER_SIZE: usize = 11;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FlvHeader {
+    pub has_audio: bool,
+    pub has_video: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TagType {
+    Audio,
+    Video,
+    Script,
+    Other(u8),
+}
+
+impl From<u8> for TagType {
+    fn from(value: u8) -> Self {
+        match value {
+            8 => Self::Audio,
+            9 => Self::Video,
+            18 => Self::Script,
+            _ => Self::Other(value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlvTag {
+    pub tag_type: TagType,
+    /// Milliseconds
+    pub timestamp: u32,
+    /// The whole tag including the tag header and the following previous tag size.
+    pub raw: Vec<u8>,
+}
+
+impl FlvTag {
+    pub fn data(&self) -> &[u8] {
+        &self.raw[TAG_HEADER_SIZE..self.raw.len() - PREVIOUS_TAG_SIZE_SIZE]
+    }
+
+    pub fn is_keyframe(&self) -> bool {
+        self.tag_type == TagType::Video && self.data().first().map(|x| x >> 4) == Some(1)
+    }
+
+    /// AVC or AAC decoder configuration, which players need before any frame.
+    pub fn is_sequence_header(&self) -> bool {
+        let data = self.data();
+        match self.tag_type {
+            TagType::Video => data.len() >= 2 && data[0] & 0x0f == 7 && data[1] == 0,
+            TagType::Audio => data.len() >= 2 && data[0] >> 4 == 10 && data[1] == 0,
+            _ => false,
+        }
+    }
+
+    /// Tags which belong to the `pkt` head together with the FLV header.
+    pub fn is_head(&self) -> bool {
+        self.tag_type == TagType::Script || self.is_sequence_header()
+    }
+
+    pub fn on_meta_data(&self) -> Result<Option<OnMetaData>> {
+        if self.tag_type != TagType::Script {
+            return Ok(None);
+        }
+        parse_on_meta_data(self.data())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FlvEvent {
+    Header { header: FlvHeader, raw: Vec<u8> },
+    Tag(FlvTag),
+}
+
+/// Splits an FLV byte stream, which may arrive in arbitrary chunks, into the header and tags.
+#[derive(Default)]
+pub struct FlvDemuxer {
+    buf: Vec<u8>,
+    header_read: bool,
+}
+
+impl FlvDemuxer {
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<FlvEvent>> {
+        self.buf.extend_from_slice(data);
+        let mut events = Vec::new();
+        if !self.header_read {
+            let Some(event) = self.read_header()? else {
+                return Ok(events);
+            };
+            events.push(event);
+        }
+        while let Some(tag) = self.read_tag() {
+            events.push(FlvEvent::Tag(tag));
+        }
+        Ok(events)
+    }
+
+    fn read_header(&mut self) -> Result<Option<FlvEvent>> {
+        if self.buf.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+        if &self.buf[0..3] != b"FLV" {
+            bail!("not an FLV stream");
+        }
+        let flags = self.buf[4];
+        let size = u32::from_be_bytes([self.buf[5], self.buf[6], self.buf[7], self.buf[8]]);
+        let size = size as usize + PREVIOUS_TAG_SIZE_SIZE;
+        if self.buf.len() < size {
+            return Ok(None);
+        }
+        self.header_read = true;
+        let raw = self.buf.drain(..size).collect();
+        let header = FlvHeader {
+            has_audio: flags & 0x04 != 0,
+            has_video: flags & 0x01 != 0,
+        };
+        Ok(Some(FlvEvent::Header { header, raw }))
+    }
+
+    fn read_tag(&mut self) -> Option<FlvTag> {
+        if self.buf.len() < TAG_HEADER_SIZE {
+            return None;
+        }
+        let b = &self.buf;
+        let data_size = u32::from_be_bytes([0, b[1], b[2], b[3]]) as usize;
+        let size = TAG_HEADER_SIZE + data_size + PREVIOUS_TAG_SIZE_SIZE;
+        if self.buf.len() < size {
+            return None;
+        }
+        let tag_type = TagType::from(b[0] & 0x1f);
+        let timestamp = u32::from_be_bytes([b[7], b[4], b[5], b[6]]);
+        let raw = self.buf.drain(..size).collect();
+        Some(FlvTag {
+            tag_type,
+            timestamp,
+            raw,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AmfValue {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    Object(Vec<(String, AmfValue)>),
+    Null,
+    Undefined,
+    EcmaArray(Vec<(String, AmfValue)>),
+    StrictArray(Vec<AmfValue>),
+    Date(f64),
+}
+
+impl AmfValue {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(x) => Some(*x),
+            _ => None,
+        }
+    }
+}
+
+/// Nesting of objects and arrays deeper than this is rejected instead of exhausting the stack.
+const MAX_AMF_DEPTH: usize = 64;
+
+struct AmfReader<'a>(&'a [u8]);
+
+impl<'a> AmfReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            bail!("unexpected end of AMF data");
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let buf = self.take(2)?;
+        Ok(u16::from_be_bytes([buf[0], buf[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let buf = self.take(4)?;
+        Ok(u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]))
+    }
+
+    fn f64(&mut self) -> Result<f64> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(f64::from_be_bytes(buf))
+    }
+
+    fn string(&mut self, len: usize) -> Result<String> {
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    /// Key-value pairs terminated by an empty key and the object end marker.
+    fn properties(&mut self, depth: usize) -> Result<Vec<(String, AmfValue)>> {
+        let mut properties = Vec::new();
+        loop {
+            let len = self.u16()? as usize;
+            if len == 0 && self.0.first() == Some(&0x09) {
+                self.u8()?;
+                return Ok(properties);
+            }
+            let key = self.string(len)?;
+            properties.push((key, self.value(depth)?));
+        }
+    }
+
+    /// `depth` is the number of objects and arrays containing the value.
+    fn value(&mut self, depth: usize) -> Result<AmfValue> {
+        if depth >= MAX_AMF_DEPTH {
+            bail!("AMF data nested deeper than {}", MAX_AMF_DEPTH);
+        }
+        Ok(match self.u8()? {
+            0x00 => AmfValue::Number(self.f64()?),
+            0x01 => AmfValue::Boolean(self.u8()? != 0),
+            0x02 => {
+                let len = self.u16()? as usize;
+                AmfValue::String(self.string(len)?)
+            }
+            0x03 => AmfValue::Object(self.properties(depth + 1)?),
+            0x05 => AmfValue::Null,
+            0x06 => AmfValue::Undefined,
+            0x08 => {
+                // the count is only a hint, the array ends with the object end marker
+                self.u32()?;
+                AmfValue::EcmaArray(self.properties(depth + 1)?)
+            }
+            0x0a => {
+                let len = self.u32()?;
+                let values = (0..len)
+                    .map(|_| self.value(depth + 1))
+                    .collect::<Result<_>>()?;
+                AmfValue::StrictArray(values)
+            }
+            0x0b => {
+                let date = self.f64()?;
+                self.u16()?;
+                AmfValue::Date(date)
+            }
+            0x0c => {
+                let len = self.u32()? as usize;
+                AmfValue::String(self.string(len)?)
+            }
+            marker => bail!("unsupported AMF0 marker: {:#04x}", marker),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VideoCodec {
+    SorensonH263,
+    Vp6,
+    Avc,
+    Other(String),
+}
+
+impl VideoCodec {
+    fn from_amf(value: &AmfValue) -> Self {
+        match value {
+            AmfValue::Number(x) if *x == 2.0 => Self::SorensonH263,
+            AmfValue::Number(x) if *x == 4.0 || *x == 5.0 => Self::Vp6,
+            AmfValue::Number(x) if *x == 7.0 => Self::Avc,
+            AmfValue::String(x) if x == "avc1" => Self::Avc,
+            AmfValue::Number(x) => Self::Other(x.to_string()),
+            AmfValue::String(x) => Self::Other(x.clone()),
+            _ => Self::Other("".into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AudioCodec {
+    Mp3,
+    Nellymoser,
+    Aac,
+    Speex,
+    Other(String),
+}
+
+impl AudioCodec {
+    fn from_amf(value: &AmfValue) -> Self {
+        match value {
+            AmfValue::Number(x) if *x == 2.0 || *x == 14.0 => Self::Mp3,
+            AmfValue::Number(x) if (4.0..=6.0).contains(x) => Self::Nellymoser,
+            AmfValue::Number(x) if *x == 10.0 => Self::Aac,
+            AmfValue::Number(x) if *x == 11.0 => Self::Speex,
+            AmfValue::String(x) if x == "mp4a" => Self::Aac,
+            AmfValue::String(x) if x == ".mp3" => Self::Mp3,
+            AmfValue::Number(x) => Self::Other(x.to_string()),
+            AmfValue::String(x) => Self::Other(x.clone()),
+            _ => Self::Other("".into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OnMetaData {
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    pub framerate: Option<f64>,
+    /// kbps
+    pub videodatarate: Option<f64>,
+    /// kbps
+    pub audiodatarate: Option<f64>,
+    pub video_codec: Option<VideoCodec>,
+    pub audio_codec: Option<AudioCodec>,
+    pub properties: Vec<(String, AmfValue)>,
+}
+
+/// Decodes the body of an `onMetaData` script tag. Other script tags return `None`.
+pub fn parse_on_meta_data(data: &[u8]) -> Result<Option<OnMetaData>> {
+    let mut reader = AmfReader(data);
+    if reader.value(0)? != AmfValue::String("onMetaData".into()) {
+        return Ok(None);
+    }
+    let properties = match reader.value(0)? {
+        AmfValue::EcmaArray(x) | AmfValue::Object(x) => x,
+        _ => bail!("onMetaData is not an object"),
+    };
+    let get = |key: &str| properties.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+    Ok(Some(OnMetaData {
+        width: get("width").and_then(AmfValue::as_f64),
+        height: get("height").and_then(AmfValue::as_f64),
+        framerate: get("framerate").and_then(AmfValue::as_f64),
+        videodatarate: get("videodatarate").and_then(AmfValue::as_f64),
+        audiodatarate: get("audiodatarate").and_then(AmfValue::as_f64),
+        video_codec: get("videocodecid").map(VideoCodec::from_amf),
+        audio_codec: get("audiocodecid").map(AudioCodec::from_amf),
+        properties,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_on_meta_data, AudioCodec, FlvDemuxer, FlvEvent, FlvHeader, TagType, VideoCodec,
+        MAX_AMF_DEPTH,
+    };
+
+    fn tag(tag_type: u8, timestamp: u32, data: &[u8]) -> Vec<u8> {
+        let mut buf = vec![tag_type];
+        buf.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+        buf.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+        buf.push((timestamp >> 24) as u8);
+        buf.extend_from_slice(&[0, 0, 0]);
+        buf.extend_from_slice(data);
+        buf.extend_from_slice(&(11 + data.len() as u32).to_be_bytes());
+        buf
+    }
+
+    fn amf_number(key: &str, value: f64) -> Vec<u8> {
+        let mut buf = (key.len() as u16).to_be_bytes().to_vec();
+        buf.extend_from_slice(key.as_bytes());
+        buf.push(0x00);
+        buf.extend_from_slice(&value.to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn test_demux() {
+        let mut script = vec![0x02, 0x00, 0x0a];
+        script.extend_from_slice(b"onMetaData");
+        script.extend_from_slice(&[0x08, 0, 0, 0, 5]);
+        script.extend(amf_number("width", 1280.0));
+        script.extend(amf_number("height", 720.0));
+        script.extend(amf_number("framerate", 30.0));
+        script.extend(amf_number("videocodecid", 7.0));
+        script.extend(amf_number("audiocodecid", 10.0));
+        script.extend_from_slice(&[0x00, 0x00, 0x09]);
+
+        let mut stream = b"FLV\x01\x05\x00\x00\x00\x09\x00\x00\x00\x00".to_vec();
+        stream.extend(tag(18, 0, &script));
+        stream.extend(tag(9, 0, &[0x17, 0x00, 0x00, 0x00, 0x00]));
+        stream.extend(tag(9, 0x01000010, &[0x17, 0x01, 0x00, 0x00, 0x00]));
+        stream.extend(tag(9, 0x01000020, &[0x27, 0x01, 0x00, 0x00, 0x00]));
+
+        let mut demuxer = FlvDemuxer::default();
+        let events = stream
+            .chunks(7)
+            .flat_map(|chunk| demuxer.push(chunk).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 5);
+        let FlvEvent::Header { header, raw } = &events[0] else {
+            panic!()
+        };
+        assert_eq!(
+            header,
+            &FlvHeader {
+                has_audio: true,
+                has_video: true,
+            }
+        );
+        assert_eq!(raw.len(), 13);
+        let tags = events[1..]
+            .iter()
+            .map(|event| match event {
+                FlvEvent::Tag(tag) => tag,
+                FlvEvent::Header { .. } => panic!(),
+            })
+            .collect::<Vec<_>>();
+
+        let meta = tags[0].on_meta_data().unwrap().unwrap();
+        assert_eq!(meta.width, Some(1280.0));
+        assert_eq!(meta.height, Some(720.0));
+        assert_eq!(meta.framerate, Some(30.0));
+        assert_eq!(meta.video_codec, Some(VideoCodec::Avc));
+        assert_eq!(meta.audio_codec, Some(AudioCodec::Aac));
+
+        assert_eq!(
+            tags.iter().map(|x| x.is_head()).collect::<Vec<_>>(),
+            [true, true, false, false]
+        );
+        assert_eq!(
+            tags.iter().map(|x| x.is_keyframe()).collect::<Vec<_>>(),
+            [false, true, true, false]
+        );
+        assert_eq!(tags[2].tag_type, TagType::Video);
+        assert_eq!(tags[2].timestamp, 0x01000010);
+        assert_eq!(tags[3].data(), [0x27, 0x01, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_nested_meta_data() {
+        // onMetaData { a: { a: ... } } nested `depth` objects deep
+        let nested = |depth: usize| {
+            let mut data = vec![0x02, 0x00, 0x0a];
+            data.extend_from_slice(b"onMetaData");
+            data.push(0x03);
+            for _ in 1..depth {
+                data.extend_from_slice(&[0x00, 0x01, b'a', 0x03]);
+            }
+            for _ in 0..depth {
+                data.extend_from_slice(&[0x00, 0x00, 0x09]);
+            }
+            data
+        };
+        assert!(parse_on_meta_data(&nested(MAX_AMF_DEPTH))
+            .unwrap()
+            .is_some());
+        assert!(parse_on_meta_data(&nested(MAX_AMF_DEPTH + 1)).is_err());
+        // fails on the limit rather than overflowing the stack
+        assert!(parse_on_meta_data(&nested(1_000_000)).is_err());
+    }
+}
@@ -0,0 +1,417 @@
+use anyhow::{bail, Result};
+
+pub const EBML: u32 = 0x1a45dfa3;
+pub const SEGMENT: u32 = 0x18538067;
+pub const CLUSTER: u32 = 0x1f43b675;
+pub const TRACKS: u32 = 0x1654ae6b;
+pub const TRACK_ENTRY: u32 = 0xae;
+pub const TRACK_NUMBER: u32 = 0xd7;
+pub const TRACK_TYPE: u32 = 0x83;
+pub const CODEC_ID: u32 = 0x86;
+pub const VIDEO: u32 = 0xe0;
+pub const PIXEL_WIDTH: u32 = 0xb0;
+pub const PIXEL_HEIGHT: u32 = 0xba;
+pub const AUDIO: u32 = 0xe1;
+pub const SAMPLING_FREQUENCY: u32 = 0xb5;
+pub const CHANNELS: u32 = 0x9f;
+pub const SIMPLE_BLOCK: u32 = 0xa3;
+pub const BLOCK_GROUP: u32 = 0xa0;
+pub const BLOCK: u32 = 0xa1;
+pub const REFERENCE_BLOCK: u32 = 0xfb;
+
+/// Larger elements are rejected instead of being buffered.
+const MAX_ELEMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Reads a variable length integer and returns `(value with the length marker, length)`.
+fn read_vint(buf: &[u8], max_len: usize) -> Result<Option<(u64, usize)>> {
+    let Some(&first) = buf.first() else {
+        return Ok(None);
+    };
+    let len = first.leading_zeros() as usize + 1;
+    if len > max_len {
+        bail!("invalid variable length integer: {:#04x}", first);
+    }
+    if buf.len() < len {
+        return Ok(None);
+    }
+    let value = buf[..len]
+        .iter()
+        .fold(0u64, |acc, &x| (acc << 8) | x as u64);
+    Ok(Some((value, len)))
+}
+
+struct ElementHeader {
+    id: u32,
+    /// `None` for the unknown size used by live streams.
+    size: Option<u64>,
+    header_len: usize,
+}
+
+fn read_element_header(buf: &[u8]) -> Result<Option<ElementHeader>> {
+    let Some((id, id_len)) = read_vint(buf, 4)? else {
+        return Ok(None);
+    };
+    let Some((size, size_len)) = read_vint(&buf[id_len..], 8)? else {
+        return Ok(None);
+    };
+    let marker = 1u64 << (7 * size_len);
+    let size = size & (marker - 1);
+    let size = (size != marker - 1).then_some(size);
+    Ok(Some(ElementHeader {
+        id: id as u32,
+        size,
+        header_len: id_len + size_len,
+    }))
+}
+
+/// Iterates the child elements of a fully buffered element body.
+fn children(mut buf: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    std::iter::from_fn(move || {
+        let header = read_element_header(buf).ok()??;
+        let end = header.header_len + header.size? as usize;
+        if buf.len() < end {
+            return None;
+        }
+        let data = &buf[header.header_len..end];
+        buf = &buf[end..];
+        Some((header.id, data))
+    })
+}
+
+fn read_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |acc, &x| (acc << 8) | x as u64)
+}
+
+fn read_float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f32::from_be_bytes(data.try_into().ok()?) as f64),
+        8 => Some(f64::from_be_bytes(data.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TrackType {
+    Video,
+    Audio,
+    Subtitle,
+    Other(u64),
+}
+
+impl From<u64> for TrackType {
+    fn from(value: u64) -> Self {
+        match value {
+            1 => Self::Video,
+            2 => Self::Audio,
+            0x11 => Self::Subtitle,
+            _ => Self::Other(value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track {
+    pub number: u64,
+    pub track_type: TrackType,
+    /// e.g. `V_VP8`, `A_OPUS`
+    pub codec_id: String,
+    pub pixel_width: Option<u64>,
+    pub pixel_height: Option<u64>,
+    pub sampling_frequency: Option<f64>,
+    pub channels: Option<u64>,
+}
+
+impl Track {
+    fn parse(entry: &[u8]) -> Self {
+        let mut track = Track {
+            number: 0,
+            track_type: TrackType::Other(0),
+            codec_id: "".into(),
+            pixel_width: None,
+            pixel_height: None,
+            sampling_frequency: None,
+            channels: None,
+        };
+        for (id, data) in children(entry) {
+            match id {
+                TRACK_NUMBER => track.number = read_uint(data),
+                TRACK_TYPE => track.track_type = read_uint(data).into(),
+                CODEC_ID => {
+                    track.codec_id = String::from_utf8_lossy(data)
+                        .trim_end_matches('\0')
+                        .to_owned()
+                }
+                VIDEO => {
+                    for (id, data) in children(data) {
+                        match id {
+                            PIXEL_WIDTH => track.pixel_width = Some(read_uint(data)),
+                            PIXEL_HEIGHT => track.pixel_height = Some(read_uint(data)),
+                            _ => {}
+                        }
+                    }
+                }
+                AUDIO => {
+                    for (id, data) in children(data) {
+                        match id {
+                            SAMPLING_FREQUENCY => track.sampling_frequency = read_float(data),
+                            CHANNELS => track.channels = Some(read_uint(data)),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        track
+    }
+}
+
+pub fn parse_tracks(tracks: &[u8]) -> Vec<Track> {
+    children(tracks)
+        .filter(|(id, _)| *id == TRACK_ENTRY)
+        .map(|(_, entry)| Track::parse(entry))
+        .collect()
+}
+
+/// Track number and keyframe flag of a `SimpleBlock` or `Block` body.
+fn read_block(data: &[u8]) -> Option<(u64, u8)> {
+    let (track, len) = read_vint(data, 8).ok()??;
+    let track = track & ((1 << (7 * len)) - 1);
+    let flags = *data.get(len + 2)?;
+    Some((track, flags))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MkvEvent {
+    /// EBML header, Segment header and every Segment child before the first Cluster.
+    Head { raw: Vec<u8>, tracks: Vec<Track> },
+    /// The Cluster element header only; its children follow as separate events.
+    ClusterStart { raw: Vec<u8> },
+    Block {
+        raw: Vec<u8>,
+        track_number: u64,
+        keyframe: bool,
+    },
+    /// Any other complete element, e.g. Timecode or Cues.
+    Element { id: u32, raw: Vec<u8> },
+}
+
+/// Splits a Matroska/WebM byte stream, which may arrive in arbitrary chunks, into the head and
+/// the elements of the clusters.
+#[derive(Default)]
+pub struct MkvDemuxer {
+    buf: Vec<u8>,
+    head: Vec<u8>,
+    tracks: Vec<Track>,
+    head_sent: bool,
+}
+
+impl MkvDemuxer {
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<MkvEvent>> {
+        self.buf.extend_from_slice(data);
+        let mut events = Vec::new();
+        while let Some(event) = self.read_event()? {
+            events.extend(event);
+        }
+        Ok(events)
+    }
+
+    /// Returns `Some(None)` when an element was consumed without producing an event.
+    fn read_event(&mut self) -> Result<Option<Option<MkvEvent>>> {
+        let Some(header) = read_element_header(&self.buf)? else {
+            return Ok(None);
+        };
+        if !self.head_sent && self.head.is_empty() && header.id != EBML {
+            bail!("not an EBML stream");
+        }
+        if !self.head_sent && header.id == CLUSTER {
+            self.head_sent = true;
+            let raw = std::mem::take(&mut self.head);
+            let tracks = std::mem::take(&mut self.tracks);
+            return Ok(Some(Some(MkvEvent::Head { raw, tracks })));
+        }
+        if header.id == SEGMENT || header.id == CLUSTER {
+            // descend into the children
+            let raw: Vec<u8> = self.buf.drain(..header.header_len).collect();
+            if header.id == CLUSTER {
+                return Ok(Some(Some(MkvEvent::ClusterStart { raw })));
+            }
+            self.head.extend(raw);
+            return Ok(Some(None));
+        }
+        let Some(size) = header.size else {
+            bail!("unknown size is not supported for {:#x}", header.id);
+        };
+        if size > MAX_ELEMENT_SIZE {
+            bail!("element too large: {:#x} ({} bytes)", header.id, size);
+        }
+        let end = header.header_len + size as usize;
+        if self.buf.len() < end {
+            return Ok(None);
+        }
+        let raw: Vec<u8> = self.buf.drain(..end).collect();
+        let data = &raw[header.header_len..];
+        if !self.head_sent {
+            if header.id == TRACKS {
+                self.tracks = parse_tracks(data);
+            }
+            self.head.extend(raw);
+            return Ok(Some(None));
+        }
+        let event = match header.id {
+            SIMPLE_BLOCK => {
+                let (track_number, flags) = read_block(data).unwrap_or_default();
+                MkvEvent::Block {
+                    track_number,
+                    keyframe: flags & 0x80 != 0,
+                    raw,
+                }
+            }
+            BLOCK_GROUP => {
+                // a Block without ReferenceBlock does not depend on other frames
+                let block = children(data).find(|(id, _)| *id == BLOCK);
+                let (track_number, _) = block.and_then(|(_, x)| read_block(x)).unwrap_or_default();
+                let keyframe = children(data).all(|(id, _)| id != REFERENCE_BLOCK);
+                MkvEvent::Block {
+                    track_number,
+                    keyframe,
+                    raw,
+                }
+            }
+            id => MkvEvent::Element { id, raw },
+        };
+        Ok(Some(Some(event)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MkvDemuxer, MkvEvent, Track, TrackType};
+
+    fn element(id: u32, data: &[u8]) -> Vec<u8> {
+        let mut buf = id
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&x| x == 0)
+            .collect::<Vec<_>>();
+        buf.push(0x01);
+        buf.extend_from_slice(&(data.len() as u64).to_be_bytes()[1..]);
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    fn unknown_size(id: u32) -> Vec<u8> {
+        let mut buf = id.to_be_bytes().to_vec();
+        buf.push(0xff);
+        buf
+    }
+
+    #[test]
+    fn test_demux() {
+        let ebml = element(0x1a45dfa3, &element(0x4282, b"webm"));
+        let segment = unknown_size(0x18538067);
+        let info = element(0x1549a966, &element(0x2ad7b1, &[0x0f, 0x42, 0x40]));
+        let video = [
+            element(0xd7, &[1]),
+            element(0x83, &[1]),
+            element(0x86, b"V_VP8"),
+            element(
+                0xe0,
+                &[element(0xb0, &[0x02, 0x80]), element(0xba, &[0x01, 0x68])].concat(),
+            ),
+        ]
+        .concat();
+        let audio = [
+            element(0xd7, &[2]),
+            element(0x83, &[2]),
+            element(0x86, b"A_OPUS"),
+            element(
+                0xe1,
+                &[element(0xb5, &48000f64.to_be_bytes()), element(0x9f, &[2])].concat(),
+            ),
+        ]
+        .concat();
+        let tracks = element(
+            0x1654ae6b,
+            &[element(0xae, &video), element(0xae, &audio)].concat(),
+        );
+        let cluster = unknown_size(0x1f43b675);
+        let timecode = element(0xe7, &[0]);
+        let keyframe = element(0xa3, &[0x81, 0x00, 0x00, 0x80, 0xaa]);
+        let interframe = element(0xa3, &[0x81, 0x00, 0x21, 0x00, 0xbb]);
+        let block_group = element(
+            0xa0,
+            &[
+                element(0xa1, &[0x82, 0x00, 0x00, 0x00]),
+                element(0xfb, &[0xff]),
+            ]
+            .concat(),
+        );
+        let stream = [
+            ebml.clone(),
+            segment.clone(),
+            info.clone(),
+            tracks.clone(),
+            cluster.clone(),
+            timecode.clone(),
+            keyframe.clone(),
+            interframe.clone(),
+            block_group.clone(),
+        ]
+        .concat();
+
+        let mut demuxer = MkvDemuxer::default();
+        let events = stream
+            .chunks(5)
+            .flat_map(|chunk| demuxer.push(chunk).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                MkvEvent::Head {
+                    raw: [ebml, segment, info, tracks].concat(),
+                    tracks: vec![
+                        Track {
+                            number: 1,
+                            track_type: TrackType::Video,
+                            codec_id: "V_VP8".into(),
+                            pixel_width: Some(640),
+                            pixel_height: Some(360),
+                            sampling_frequency: None,
+                            channels: None,
+                        },
+                        Track {
+                            number: 2,
+                            track_type: TrackType::Audio,
+                            codec_id: "A_OPUS".into(),
+                            pixel_width: None,
+                            pixel_height: None,
+                            sampling_frequency: Some(48000.0),
+                            channels: Some(2),
+                        },
+                    ],
+                },
+                MkvEvent::ClusterStart { raw: cluster },
+                MkvEvent::Element {
+                    id: 0xe7,
+                    raw: timecode
+                },
+                MkvEvent::Block {
+                    raw: keyframe,
+                    track_number: 1,
+                    keyframe: true
+                },
+                MkvEvent::Block {
+                    raw: interframe,
+                    track_number: 1,
+                    keyframe: false
+                },
+                MkvEvent::Block {
+                    raw: block_group,
+                    track_number: 2,
+                    keyframe: false
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::TcpListener;
+
+/// Listens on `port` of both IPv6 and IPv4, or of IPv4 only where IPv6 isn't available.
+pub fn bind_dual_stack(port: u16) -> io::Result<TcpListener> {
+    let bind = |addr: SocketAddr| -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(false)?;
+        }
+        // Same as `TcpListener::bind`
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        socket.set_nonblocking(true)?;
+        TcpListener::from_std(socket.into())
+    };
+    bind(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port)).or_else(|err| {
+        tracing::debug!("Falling back to IPv4: {}", err);
+        bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port))
+    })
+}
+
+/// `addr` with an IPv4-mapped IPv6 address, as a dual-stack listener accepts IPv4 peers, as IPv4.
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
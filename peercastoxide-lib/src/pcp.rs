@@ -1 +1,4 @@
 pub mod atom;
+pub mod giv;
+pub mod http;
+pub mod relay_candidates;
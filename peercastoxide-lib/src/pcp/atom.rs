@@ -1,6 +1,7 @@
 mod atom_stream;
 mod de;
 mod ser;
+pub mod text;
 mod unknown;
 pub mod values;
 pub mod well_known_atoms;
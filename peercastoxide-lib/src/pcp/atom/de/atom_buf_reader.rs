@@ -1,4 +1,4 @@
-use std::io::Read;
+use std::io::{self, Read};
 
 use anyhow::{anyhow, Result};
 
@@ -101,6 +101,25 @@ impl<R: Read> AtomBufReader<R> {
         Ok(buf)
     }
 
+    /// Skips the rest of an atom whose identifier has been read, with its descendants.
+    pub fn skip_atom(&mut self) -> Result<(), AtomDeserializeError> {
+        // Counts the atoms left instead of recursing so that deep nesting can't overflow the stack.
+        let mut remaining = 1u64;
+        loop {
+            let (is_parent, length) = self.read_length()?;
+            remaining -= 1;
+            if is_parent {
+                remaining += length as u64;
+            } else {
+                io::copy(&mut (&mut self.reader).take(length as u64), &mut io::sink())?;
+            }
+            if remaining == 0 {
+                return Ok(());
+            }
+            self.read_identifier()?;
+        }
+    }
+
     pub fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), AtomDeserializeError> {
         self.reader
             .read_exact(buf)
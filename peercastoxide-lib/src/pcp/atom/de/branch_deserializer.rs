@@ -70,6 +70,16 @@ impl<'a, 'de, R: Read> Deserializer<'de> for &mut BranchDeserializer<'a, R> {
 
     common_unsupported_deserializes! {}
 
+    /// Skips a child the struct doesn't have a field for.
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.reader.skip_atom()?;
+        self.remaining -= 1;
+        visitor.visit_unit()
+    }
+
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
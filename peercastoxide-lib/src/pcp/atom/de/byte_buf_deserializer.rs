@@ -48,6 +48,13 @@ impl<'de> Deserializer<'de> for &mut ByteBufDeserializer {
 
     common_unsupported_deserializes! {}
 
+    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(AtomDeserializeError::unsupported_structure("ignored_any"))
+    }
+
     fn deserialize_u16<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
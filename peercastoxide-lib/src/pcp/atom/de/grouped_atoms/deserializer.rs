@@ -49,6 +49,13 @@ impl<'a, 'de, R: Read> Deserializer<'de> for &mut GroupedAtomsDeserializer<'a, R
 
     common_unsupported_deserializes! {}
 
+    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(AtomDeserializeError::unsupported_structure("ignored_any"))
+    }
+
     fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
@@ -103,16 +110,32 @@ impl<'a, 'de, R: Read> Deserializer<'de> for &mut GroupedAtomsDeserializer<'a, R
     {
         Err(AtomDeserializeError::unsupported_structure("seq"))
     }
+    /// Fields are read in order, and their names must match the grouped atoms.
     fn deserialize_struct<V>(
         self,
         _name: &'static str,
-        _fields: &'static [&'static str],
-        _visitor: V,
+        fields: &'static [&'static str],
+        visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        Err(AtomDeserializeError::unsupported_structure("struct"))
+        let names = self
+            .grouped_atoms
+            .iter()
+            .map(|x| x.split(|&x| x == 0).next().unwrap_or_default());
+        if !fields.iter().map(|x| x.as_bytes()).eq(names) {
+            let err = anyhow::anyhow!(
+                "fields {:?} don't match grouped atoms {}",
+                fields,
+                self.grouped_atoms
+                    .iter()
+                    .map(|x| String::from_utf8_lossy(x))
+                    .collect::<String>()
+            );
+            return Err(AtomDeserializeError::UnsupportedStructure(err));
+        }
+        self.deserialize_tuple(fields.len(), visitor)
     }
     fn deserialize_enum<V>(
         self,
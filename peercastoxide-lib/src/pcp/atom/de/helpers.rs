@@ -106,11 +106,5 @@ macro_rules! common_unsupported_deserializes {
         {
             Err(AtomDeserializeError::unsupported_structure("map"))
         }
-        fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
-        where
-            V: Visitor<'de>,
-        {
-            Err(AtomDeserializeError::unsupported_structure("ignored_any"))
-        }
     };
 }
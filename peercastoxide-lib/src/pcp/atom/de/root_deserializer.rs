@@ -66,6 +66,13 @@ impl<'de, R: Read> Deserializer<'de> for RootDeserializer<R> {
 
     common_unsupported_deserializes! {}
 
+    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(AtomDeserializeError::unsupported_structure("ignored_any"))
+    }
+
     fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
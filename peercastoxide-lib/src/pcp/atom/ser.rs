@@ -50,8 +50,8 @@ mod tests {
     use crate::pcp::atom::{
         self,
         ser::AtomSerializeError,
-        values::{AtomIpAddr, Flg1, Id, VExP},
-        well_known_atoms::{Bcst, Chan, Helo, Host, Info, Oleh, Pcp, Trck},
+        values::{AtomIpAddr, BcstGroup, Endpoint, Flg1, Id, PktType, VExP},
+        well_known_atoms::{Bcst, Chan, Helo, Host, Info, Oleh, Pcp, Pkt, Push, StreamChan, Trck},
     };
 
     #[test]
@@ -65,6 +65,18 @@ mod tests {
         assert!(matches!(err, AtomSerializeError::UnsupportedStructure(_)));
     }
 
+    #[test]
+    fn test_count_children_of_some() {
+        let push = Push {
+            ip: AtomIpAddr::from([127, 0, 0, 1]),
+            port: 7144,
+            cid: Id([1; 16]),
+        };
+        assert_eq!(super::count_children(&push).unwrap(), 3);
+        // An `Option` field holding a struct is a parent of the struct's children
+        assert_eq!(super::count_children(Some(&push)).unwrap(), 3);
+    }
+
     #[test]
     fn test_to_writer_pcp() {
         let before = Pcp(1);
@@ -151,19 +163,41 @@ mod tests {
         assert_eq!(before, after);
     }
 
+    #[test]
+    fn test_to_writer_stream_chan_pkt() {
+        let before = StreamChan {
+            id: Id([1; 16]),
+            bcid: None,
+            info: None,
+            trck: None,
+            pkt: Some(Pkt {
+                r#type: PktType::DATA,
+                pos: 1234,
+                data: vec![1, 2, 3, 4, 5],
+                cont: None,
+            }),
+        };
+        let mut buf = Vec::new();
+        atom::ser::to_writer(&mut buf, &before).unwrap();
+        assert_eq!(&buf[0..8], b"chan\x02\x00\x00\x80");
+        let after: StreamChan = atom::de::from_reader(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(before, after);
+    }
+
     #[test]
     fn test_to_writer_bcst() {
         let before = Bcst {
-            grp: 1,
+            grp: BcstGroup::ROOT | BcstGroup::TRACKERS,
             hops: 2,
             ttl: 3,
             from: Id([1; 16]),
+            dest: Some(Id([7; 16])),
             vers: 4,
             vrvp: 5,
             vexp: VExP([b'V', b'P']),
             vexn: 6,
             cid: Some(Id([6; 16])),
-            chan: Chan {
+            chan: Some(Chan {
                 id: Id([2; 16]),
                 bcid: Id([3; 16]),
                 info: Info {
@@ -184,16 +218,21 @@ mod tests {
                     albm: "album".into(),
                     gnre: Some("genre".into()),
                 },
-            },
-            host: Host {
+            }),
+            host: Some(Host {
                 cid: Id([4; 16]),
                 id: Id([5; 16]),
                 ip_port: vec![
-                    (AtomIpAddr::from([1, 2, 3, 4]), 5),
-                    (
-                        AtomIpAddr::from([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]),
-                        6,
-                    ),
+                    Endpoint {
+                        ip: AtomIpAddr::from([1, 2, 3, 4]),
+                        port: 5,
+                    },
+                    Endpoint {
+                        ip: AtomIpAddr::from([
+                            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+                        ]),
+                        port: 6,
+                    },
                 ],
                 numl: 7,
                 numr: 8,
@@ -208,7 +247,106 @@ mod tests {
                 upip: Some(AtomIpAddr::from([1, 2, 3, 4])),
                 uppt: Some(15),
                 uphp: Some(16),
-            },
+            }),
+            push: None,
+        };
+        let mut buf = Vec::new();
+        atom::ser::to_writer(&mut buf, &before).unwrap();
+        let atom: Bcst = atom::de::from_reader(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(before, atom);
+        let mut host = atom.host.unwrap();
+        assert_eq!(host.global_v4(), Some("1.2.3.4:5".parse().unwrap()));
+        assert_eq!(host.local_v4(), None);
+        assert_eq!(
+            host.global_v6(),
+            Some("[102:304:506:708:90a:b0c:d0e:f10]:6".parse().unwrap())
+        );
+        host.add_verified_addr("[::1]:7144".parse().unwrap());
+        assert_eq!(host.ip_port.len(), 2);
+        host.ip_port.pop();
+        host.add_verified_addr("[::1]:7144".parse().unwrap());
+        assert_eq!(host.global_v4(), Some("1.2.3.4:5".parse().unwrap()));
+        assert_eq!(host.global_v6(), Some("[::1]:7144".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_to_writer_grouped_struct() {
+        #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+        struct Entry {
+            ip: AtomIpAddr,
+            port: u16,
+            uptm: u32,
+        }
+        #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+        #[serde(rename = "host")]
+        struct Parent {
+            #[serde(rename = "ip\0\0portuptm")]
+            entries: Vec<Entry>,
+        }
+
+        let before = Parent {
+            entries: vec![
+                Entry {
+                    ip: AtomIpAddr::from([1, 2, 3, 4]),
+                    port: 5,
+                    uptm: 6,
+                },
+                Entry {
+                    ip: AtomIpAddr::from([7; 16]),
+                    port: 8,
+                    uptm: 9,
+                },
+            ],
+        };
+        let mut buf = Vec::new();
+        atom::ser::to_writer(&mut buf, &before).unwrap();
+        // host with 6 children, interleaved per entry
+        assert_eq!(&buf[..8], b"host\x06\x00\x00\x80");
+        let identifiers = [8, 20, 30, 42, 66, 76].map(|i| &buf[i..i + 4]);
+        assert_eq!(
+            identifiers,
+            [b"ip\0\0", b"port", b"uptm", b"ip\0\0", b"port", b"uptm"].map(|x| &x[..])
+        );
+        let after: Parent = atom::de::from_reader(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(before, after);
+
+        #[derive(Debug, serde::Deserialize)]
+        #[allow(dead_code)]
+        struct RenamedEntry {
+            ip: AtomIpAddr,
+            port: u16,
+            time: u32,
+        }
+        #[derive(Debug, serde::Deserialize)]
+        #[serde(rename = "host")]
+        #[allow(dead_code)]
+        struct RenamedParent {
+            #[serde(rename = "ip\0\0portuptm")]
+            entries: Vec<RenamedEntry>,
+        }
+        assert!(atom::de::from_reader::<RenamedParent>(&mut Cursor::new(&buf)).is_err());
+    }
+
+    #[test]
+    fn test_to_writer_bcst_push() {
+        let before = Bcst {
+            grp: BcstGroup::ALL,
+            hops: 0,
+            ttl: 7,
+            from: Id([1; 16]),
+            dest: Some(Id([2; 16])),
+            vers: 1218,
+            vrvp: 27,
+            vexp: VExP([b'I', b'M']),
+            vexn: 51,
+            cid: None,
+            chan: None,
+            host: None,
+            push: Some(Push {
+                ip: AtomIpAddr::from([1, 2, 3, 4]),
+                port: 7144,
+                cid: Id([3; 16]),
+            }),
         };
         let mut buf = Vec::new();
         atom::ser::to_writer(&mut buf, &before).unwrap();
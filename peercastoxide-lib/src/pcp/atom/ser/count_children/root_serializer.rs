@@ -60,7 +60,8 @@ impl Serializer for &mut RootSerializer {
     where
         T: ?Sized + Serialize,
     {
-        BranchSerializer::new(None).serialize_some(v)
+        // Some(struct) の子の数は struct の子の数
+        v.serialize(self)
     }
 
     fn serialize_newtype_struct<T>(
@@ -138,7 +139,7 @@ impl SerializeStruct for &mut RootSerializer {
         T: ?Sized + Serialize,
     {
         // 5 文字以上は grouped atoms なので 1 要素以上の数になる
-        let atoms = (key.len() + 3) / 4;
+        let atoms = key.len().div_ceil(4);
         let mut child = BranchSerializer::new(if atoms == 1 { None } else { Some(atoms) });
         value.serialize(&mut child)?;
         self.result += child.result();
@@ -2,7 +2,7 @@ use std::io::Write;
 
 use anyhow::Result;
 use serde::{
-    ser::{SerializeTuple, SerializeTupleStruct},
+    ser::{SerializeStruct, SerializeTuple, SerializeTupleStruct},
     Serialize, Serializer,
 };
 
@@ -28,6 +28,31 @@ impl<'a, W: Write> GroupedAtomsSerializer<'a, W> {
             idx: 0,
         }
     }
+
+    fn check_len(&self, len: usize) -> Result<(), AtomSerializeError> {
+        if len != self.grouped_atoms.len() {
+            let err = anyhow::anyhow!(
+                "grouped atoms length expected {} but got {}",
+                self.grouped_atoms.len(),
+                len
+            );
+            return Err(AtomSerializeError::UnsupportedStructure(err));
+        }
+        Ok(())
+    }
+
+    fn serialize_next<T>(&mut self, value: &T) -> Result<(), AtomSerializeError>
+    where
+        T: ?Sized + Serialize,
+    {
+        let identifier = self.grouped_atoms[self.idx];
+        self.idx += 1;
+        value.serialize(BranchSerializer::new(
+            &mut self.writer,
+            &identifier,
+            count_children(value)?,
+        ))
+    }
 }
 
 impl<'a, W: Write> Serializer for GroupedAtomsSerializer<'a, W> {
@@ -38,7 +63,7 @@ impl<'a, W: Write> Serializer for GroupedAtomsSerializer<'a, W> {
     type SerializeTupleStruct = Self;
     type SerializeTupleVariant = UnreachableSerializer<Self::Ok>;
     type SerializeMap = UnreachableSerializer<Self::Ok>;
-    type SerializeStruct = UnreachableSerializer<Self::Ok>;
+    type SerializeStruct = Self;
     type SerializeStructVariant = UnreachableSerializer<Self::Ok>;
 
     // ----
@@ -83,25 +108,20 @@ impl<'a, W: Write> Serializer for GroupedAtomsSerializer<'a, W> {
     }
 
     fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        if len != self.grouped_atoms.len() {
-            let err = anyhow::anyhow!(
-                "grouped atoms length expected {} but got {}",
-                self.grouped_atoms.len(),
-                len
-            );
-            return Err(AtomSerializeError::UnsupportedStructure(err));
-        }
+        self.check_len(len)?;
         Ok(self)
     }
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
         Err(AtomSerializeError::unsupported_structure("map"))
     }
+    /// Fields are written in order, and their names must match the grouped atoms.
     fn serialize_struct(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
-        Err(AtomSerializeError::unsupported_structure("struct"))
+        self.check_len(len)?;
+        Ok(self)
     }
 }
 
@@ -113,13 +133,7 @@ impl<'a, W: Write> SerializeTuple for GroupedAtomsSerializer<'a, W> {
     where
         T: ?Sized + Serialize,
     {
-        let identifier = self.grouped_atoms[self.idx];
-        self.idx += 1;
-        value.serialize(BranchSerializer::new(
-            &mut self.writer,
-            &identifier,
-            count_children(value)?,
-        ))
+        self.serialize_next(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
@@ -135,13 +149,32 @@ impl<'a, W: Write> SerializeTupleStruct for GroupedAtomsSerializer<'a, W> {
     where
         T: ?Sized + Serialize,
     {
-        let identifier = self.grouped_atoms[self.idx];
-        self.idx += 1;
-        value.serialize(BranchSerializer::new(
-            &mut self.writer,
-            &identifier,
-            count_children(value)?,
-        ))
+        self.serialize_next(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(())
+    }
+}
+
+impl<'a, W: Write> SerializeStruct for GroupedAtomsSerializer<'a, W> {
+    type Ok = ();
+    type Error = AtomSerializeError;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let identifier = &self.grouped_atoms[self.idx];
+        if key.as_bytes() != identifier.split(|&x| x == 0).next().unwrap_or_default() {
+            let err = anyhow::anyhow!(
+                "field {} doesn't match grouped atom {}",
+                key,
+                String::from_utf8_lossy(identifier)
+            );
+            return Err(AtomSerializeError::UnsupportedStructure(err));
+        }
+        self.serialize_next(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
@@ -0,0 +1,402 @@
+use std::{
+    fmt::Write,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+
+use super::{unknown::Identifier, well_known_identifiers::*, AtomChild, UnknownAtom};
+
+/// A typed value of a child atom in the text form.
+#[derive(Clone, Debug, PartialEq)]
+enum Literal {
+    Str(String),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    Ip(IpAddr),
+    Hex(Vec<u8>),
+}
+
+impl Literal {
+    /// Picks the most readable literal that gives back exactly the same bytes.
+    fn guess(child: &AtomChild) -> Self {
+        let data = child.data();
+        match child.identifier().0.as_ref() {
+            PING | PORT | UPPT | VEXN if data.len() == 2 => Self::U16(child.to_u16().unwrap()),
+            PCP | BITR | NEWP | NUML | NUMR | OK | OLDP | POS | QUIT | UPHP | UPTM | VER | VERS
+            | VEVP | VRVP
+                if data.len() == 4 =>
+            {
+                Self::U32(child.to_u32().unwrap())
+            }
+            IP | RIP | UPIP if data.len() == 4 || data.len() == 16 => {
+                Self::Ip(child.to_ip().unwrap())
+            }
+            FLG1 | HOPS | TTL | GRP | CONT if data.len() == 1 => Self::U8(data[0]),
+            _ => match data.split_last() {
+                Some((0, str)) if !str.contains(&0) => match std::str::from_utf8(str) {
+                    Ok(str) => Self::Str(str.into()),
+                    Err(_) => Self::Hex(data.to_vec()),
+                },
+                _ => Self::Hex(data.to_vec()),
+            },
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Self::Str(str) => str.into_bytes().into_iter().chain([0]).collect(),
+            Self::U8(num) => vec![num],
+            Self::U16(num) => num.to_le_bytes().to_vec(),
+            Self::U32(num) => num.to_le_bytes().to_vec(),
+            Self::Ip(IpAddr::V4(ip)) => ip.octets().into_iter().rev().collect(),
+            Self::Ip(IpAddr::V6(ip)) => ip.octets().into_iter().rev().collect(),
+            Self::Hex(data) => data,
+        }
+    }
+
+    fn write(&self, out: &mut String) {
+        match self {
+            Self::Str(str) => write_quoted(out, str),
+            Self::U8(num) => write!(out, "u8:{}", num).unwrap(),
+            Self::U16(num) => write!(out, "u16:{}", num).unwrap(),
+            Self::U32(num) => write!(out, "u32:{}", num).unwrap(),
+            Self::Ip(ip) => write!(out, "ip:{}", ip).unwrap(),
+            Self::Hex(data) => {
+                out.push_str("hex:");
+                data.iter().for_each(|x| write!(out, "{:02x}", x).unwrap());
+            }
+        }
+    }
+}
+
+fn write_quoted(out: &mut String, str: &str) {
+    out.push('"');
+    for ch in str.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if ch.is_ascii_control() => write!(out, "\\x{:02x}", ch as u8).unwrap(),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+}
+
+fn write_identifier(out: &mut String, identifier: &Identifier) {
+    let bytes = identifier.0.as_ref();
+    let len = bytes.iter().rposition(|&x| x != 0).map_or(0, |x| x + 1);
+    let bytes = &bytes[..len];
+    if !bytes.is_empty() && bytes.iter().all(|x| x.is_ascii_alphanumeric()) {
+        out.push_str(std::str::from_utf8(bytes).unwrap());
+    } else {
+        // Escaped byte by byte, so any identifier survives the round trip.
+        out.push('"');
+        for &x in bytes {
+            match x {
+                b'"' | b'\\' => write!(out, "\\{}", x as char).unwrap(),
+                x if x.is_ascii_graphic() || x == b' ' => out.push(x as char),
+                x => write!(out, "\\x{:02x}", x).unwrap(),
+            }
+        }
+        out.push('"');
+    }
+}
+
+fn write_atom(out: &mut String, atom: &UnknownAtom, depth: usize) {
+    out.push_str(&"  ".repeat(depth));
+    match atom {
+        UnknownAtom::Parent(parent) => {
+            write_identifier(out, parent.identifier());
+            if parent.children().is_empty() {
+                out.push_str(" {}\n");
+                return;
+            }
+            out.push_str(" {\n");
+            for child in parent.children() {
+                write_atom(out, child, depth + 1);
+            }
+            out.push_str(&"  ".repeat(depth));
+            out.push_str("}\n");
+        }
+        UnknownAtom::Child(child) => {
+            write_identifier(out, child.identifier());
+            out.push_str(" = ");
+            Literal::guess(child).write(out);
+            out.push_str(";\n");
+        }
+    }
+}
+
+/// Dumps atoms in the text form read by [`from_text`], e.g.
+///
+/// ```text
+/// helo {
+///   sid = hex:00112233445566778899aabbccddeeff;
+///   agnt = "PeerCastOxide/0.1.0";
+///   ping = u16:7144;
+/// }
+/// ```
+pub fn to_text<'a>(atoms: impl IntoIterator<Item = &'a UnknownAtom>) -> String {
+    let mut out = String::new();
+    for atom in atoms {
+        write_atom(&mut out, atom, 0);
+    }
+    out
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, msg: impl std::fmt::Display) -> anyhow::Error {
+        let consumed = &self.src[..self.pos];
+        let line = consumed.matches('\n').count() + 1;
+        let column = consumed.rsplit('\n').next().unwrap().chars().count() + 1;
+        anyhow!("{}:{}: {}", line, column, msg)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_blank(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if !trimmed.starts_with('#') {
+                return;
+            }
+            self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+    }
+
+    fn expect(&mut self, token: char) -> Result<()> {
+        self.skip_blank();
+        if self.peek() != Some(token) {
+            return Err(self.error(format!("expected `{}`", token)));
+        }
+        self.pos += token.len_utf8();
+        Ok(())
+    }
+
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|x: char| !pred(x)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    /// Reads a `"..."` literal after the opening quote.
+    fn quoted(&mut self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        loop {
+            let Some(ch) = self.peek() else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += ch.len_utf8();
+            match ch {
+                '"' => return Ok(buf),
+                '\\' => {
+                    let escaped = self
+                        .peek()
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += escaped.len_utf8();
+                    match escaped {
+                        '"' | '\\' => buf.push(escaped as u8),
+                        'n' => buf.push(b'\n'),
+                        'r' => buf.push(b'\r'),
+                        't' => buf.push(b'\t'),
+                        'x' => {
+                            let hex = self.rest().get(..2).unwrap_or_default();
+                            let byte = u8::from_str_radix(hex, 16)
+                                .map_err(|_| self.error("invalid `\\x` escape"))?;
+                            self.pos += 2;
+                            buf.push(byte);
+                        }
+                        _ => return Err(self.error(format!("unknown escape `\\{}`", escaped))),
+                    }
+                }
+                ch => buf.extend(ch.to_string().as_bytes()),
+            }
+        }
+    }
+
+    fn identifier(&mut self) -> Result<Identifier> {
+        self.skip_blank();
+        let bytes = if self.peek() == Some('"') {
+            self.pos += 1;
+            self.quoted()?
+        } else {
+            self.take_while(|x| x.is_ascii_alphanumeric())
+                .as_bytes()
+                .to_vec()
+        };
+        if bytes.is_empty() || bytes.len() > 4 {
+            return Err(self.error("identifier must be 1 to 4 bytes"));
+        }
+        let mut identifier = [0u8; 4];
+        identifier[..bytes.len()].copy_from_slice(&bytes);
+        Ok(identifier.into())
+    }
+
+    fn number<T: FromStr>(&mut self, kind: &str) -> Result<T> {
+        let start = self.pos;
+        let token = self.take_while(|x| x.is_ascii_digit());
+        token.parse().map_err(|_| {
+            self.pos = start;
+            self.error(format!("invalid {}", kind))
+        })
+    }
+
+    fn literal(&mut self) -> Result<Literal> {
+        self.skip_blank();
+        if self.peek() == Some('"') {
+            self.pos += 1;
+            let bytes = self.quoted()?;
+            let str = String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))?;
+            return Ok(Literal::Str(str));
+        }
+        let kind = self.take_while(|x| x.is_ascii_alphanumeric());
+        if self.peek() != Some(':') {
+            return Err(self.error("expected a string or `<type>:<value>`"));
+        }
+        self.pos += 1;
+        Ok(match kind {
+            "u8" => Literal::U8(self.number("u8")?),
+            "u16" => Literal::U16(self.number("u16")?),
+            "u32" => Literal::U32(self.number("u32")?),
+            "ip" => {
+                let token = self.take_while(|x| x.is_ascii_hexdigit() || x == '.' || x == ':');
+                let ip = Ipv4Addr::from_str(token)
+                    .map(IpAddr::V4)
+                    .or_else(|_| Ipv6Addr::from_str(token).map(IpAddr::V6))
+                    .map_err(|_| self.error("invalid ip"))?;
+                Literal::Ip(ip)
+            }
+            "hex" => {
+                let token = self.take_while(|x| x.is_ascii_hexdigit());
+                if !token.len().is_multiple_of(2) {
+                    return Err(self.error("odd number of hex digits"));
+                }
+                let data = (0..token.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&token[i..i + 2], 16).unwrap())
+                    .collect();
+                Literal::Hex(data)
+            }
+            _ => return Err(self.error(format!("unknown type `{}`", kind))),
+        })
+    }
+
+    fn atom(&mut self) -> Result<UnknownAtom> {
+        let identifier = self.identifier()?;
+        self.skip_blank();
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                let mut children = Vec::new();
+                loop {
+                    self.skip_blank();
+                    if self.peek() == Some('}') {
+                        self.pos += 1;
+                        return Ok(UnknownAtom::parent(identifier, children));
+                    }
+                    if self.peek().is_none() {
+                        return Err(self.error("expected `}`"));
+                    }
+                    children.push(self.atom()?);
+                }
+            }
+            Some('=') => {
+                self.pos += 1;
+                let literal = self.literal()?;
+                self.expect(';')?;
+                Ok(UnknownAtom::child(identifier, literal.into_bytes()))
+            }
+            _ => Err(self.error("expected `{` or `=`")),
+        }
+    }
+}
+
+/// Parses atoms written by [`to_text`] or by hand. `#` starts a comment.
+pub fn from_text(src: &str) -> Result<Vec<UnknownAtom>> {
+    let mut parser = Parser { src, pos: 0 };
+    let mut atoms = Vec::new();
+    loop {
+        parser.skip_blank();
+        if parser.peek().is_none() {
+            return Ok(atoms);
+        }
+        atoms.push(parser.atom()?);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::pcp::atom::{
+        unknown::Identifier,
+        well_known_identifiers::{AGNT, BCST, HELO, HOST, IP, PORT, SID},
+        UnknownAtom,
+    };
+
+    use super::{from_text, to_text};
+
+    #[test]
+    fn test_round_trip() {
+        let atoms = vec![
+            UnknownAtom::parent(
+                HELO,
+                vec![
+                    UnknownAtom::child(SID, (0..16).collect()),
+                    UnknownAtom::str(AGNT, "Peer\"Cast\"\n\\").unwrap(),
+                    UnknownAtom::u16(PORT, 7144),
+                    UnknownAtom::ipv4(IP, &Ipv4Addr::new(192, 168, 0, 1)),
+                    UnknownAtom::ipv6(IP, &Ipv6Addr::LOCALHOST),
+                    // not terminated
+                    UnknownAtom::child(AGNT, b"abc".to_vec()),
+                    // odd sizes for known identifiers
+                    UnknownAtom::child(PORT, vec![1, 2, 3]),
+                    UnknownAtom::child(IP, vec![]),
+                ],
+            ),
+            UnknownAtom::parent(BCST, vec![UnknownAtom::parent(HOST, vec![])]),
+            UnknownAtom::child(Identifier::from(*b"a\0\"\x01"), vec![0]),
+        ];
+        let text = to_text(&atoms);
+        assert_eq!(from_text(&text).unwrap(), atoms);
+
+        let fixture = r#"
+            # hand-written
+            helo {
+              sid = hex:000102030405060708090a0b0c0d0e0f;
+              agnt = "Peer\"Cast\"\n\\"; port = u16:7144;
+              ip = ip:192.168.0.1;
+              ip = ip:::1;
+              agnt = hex:616263;
+              port = hex:010203;
+              ip = hex:;
+            }
+            bcst { host {} }
+            "a\x00\"\x01" = "";
+        "#;
+        assert_eq!(from_text(fixture).unwrap(), atoms);
+
+        let err = from_text("helo {\n  port = u16:70000;\n}").unwrap_err();
+        assert_eq!(err.to_string(), "2:14: invalid u16");
+    }
+}
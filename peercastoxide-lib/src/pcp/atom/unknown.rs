@@ -50,7 +50,7 @@ pub fn to_unknown<T: serde::Serialize>(
     Ok(atom)
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Identifier(pub Cow<'static, [u8; 4]>);
 
 impl Display for Identifier {
@@ -94,7 +94,7 @@ impl TryFrom<&str> for Identifier {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum UnknownAtom {
     Parent(AtomParent),
     Child(AtomChild),
@@ -128,6 +128,10 @@ impl UnknownAtom {
         )))
     }
 
+    pub fn u8(identifier: impl Into<Identifier>, data: u8) -> Self {
+        Self::Child(AtomChild::new(identifier.into(), vec![data]))
+    }
+
     pub fn u16(identifier: impl Into<Identifier>, data: u16) -> Self {
         Self::Child(AtomChild::new(
             identifier.into(),
@@ -9,7 +9,7 @@ use crate::pcp::atom::{values::Flg1, well_known_identifiers::*};
 
 use super::Identifier;
 
-#[derive(Debug, Eq, PartialEq, derive_new::new, getset::Getters)]
+#[derive(Clone, Debug, Eq, PartialEq, derive_new::new, getset::Getters)]
 pub struct AtomChild {
     #[get = "pub"]
     identifier: Identifier,
@@ -59,6 +59,12 @@ impl AtomChild {
         }
     }
 
+    pub fn to_u8(&self) -> Result<u8> {
+        let mut num = [0u8; 1];
+        (&mut num[0..1]).write_all(&self.data)?;
+        Ok(num[0])
+    }
+
     pub fn to_u16(&self) -> Result<u16> {
         let mut num = [0u8; 2];
         (&mut num[0..2]).write_all(&self.data)?;
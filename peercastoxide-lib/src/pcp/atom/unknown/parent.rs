@@ -5,7 +5,7 @@ use getset::Getters;
 
 use super::{Identifier, UnknownAtom};
 
-#[derive(Debug, Eq, Getters, PartialEq, new)]
+#[derive(Clone, Debug, Eq, Getters, PartialEq, new)]
 pub struct AtomParent {
     #[get = "pub"]
     identifier: Identifier,
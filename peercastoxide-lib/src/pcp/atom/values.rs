@@ -1,9 +1,13 @@
 use std::{
     fmt::{Debug, Display, Formatter},
-    net::IpAddr,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
 };
 
+use anyhow::{anyhow, bail};
+use md5::Md5;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
 
 #[derive(Clone, Eq, Hash, PartialEq, serde::Deserialize, serde::Serialize)]
 #[serde(transparent)]
@@ -21,31 +25,115 @@ impl Debug for Id {
     }
 }
 
+impl FromStr for Id {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // `from_str_radix` alone would also take a sign
+        if s.len() != 32 || !s.bytes().all(|x| x.is_ascii_hexdigit()) {
+            bail!("id must be 32 hex digits: {}", s);
+        }
+        let mut id = [0u8; 16];
+        for (i, x) in id.iter_mut().enumerate() {
+            *x = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+                .map_err(|_| anyhow!("invalid id: {}", s))?;
+        }
+        Ok(Id(id))
+    }
+}
+
+impl Id {
+    /// Channel ID derived from the broadcast ID like PeerCast's `GnuID::encode`.
+    ///
+    /// The salts are XORed byte by byte and restart after their terminating NUL, and the bitrate
+    /// is truncated to a byte. See [`Id::for_station_channel`] for PeerCastStation.
+    pub fn for_channel(broadcast_id: &Id, name: &str, genre: &str, bitrate: u32) -> Id {
+        fn salt(salt: &[u8], pos: &mut usize) -> u8 {
+            match salt.get(*pos) {
+                Some(&x) => {
+                    *pos += 1;
+                    x
+                }
+                None => {
+                    *pos = 0;
+                    0
+                }
+            }
+        }
+        let (mut s1, mut s2) = (0, 0);
+        let mut id = broadcast_id.0;
+        for x in id.iter_mut() {
+            *x ^= salt(name.as_bytes(), &mut s1);
+            *x ^= salt(genre.as_bytes(), &mut s2);
+            *x ^= bitrate as u8;
+        }
+        Id(id)
+    }
+
+    /// Channel ID derived from the broadcast ID like PeerCastStation's
+    /// `BroadcastChannel.CreateChannelID`.
+    ///
+    /// It is the MD5 of the SHA-512 of the broadcast ID followed by the name, the genre and the
+    /// source URL as .NET `BinaryWriter` strings. Both IDs are .NET `Guid`s, whose first three
+    /// fields are little endian in memory but big endian in atoms.
+    pub fn for_station_channel(broadcast_id: &Id, name: &str, genre: &str, source: &str) -> Id {
+        fn swap_guid(mut id: [u8; 16]) -> [u8; 16] {
+            id[..4].reverse();
+            id[4..6].reverse();
+            id[6..8].reverse();
+            id
+        }
+        fn write_string(buf: &mut Vec<u8>, text: &str) {
+            // the byte length as a 7-bit encoded integer
+            let mut len = text.len();
+            while len >= 0x80 {
+                buf.push(len as u8 | 0x80);
+                len >>= 7;
+            }
+            buf.push(len as u8);
+            buf.extend_from_slice(text.as_bytes());
+        }
+        let mut buf = Sha512::digest(swap_guid(broadcast_id.0)).to_vec();
+        write_string(&mut buf, name);
+        write_string(&mut buf, genre);
+        write_string(&mut buf, source);
+        Id(swap_guid(Md5::digest(&buf).into()))
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
 #[serde(transparent)]
 pub struct Flg1(pub u8);
 
 impl Flg1 {
+    pub const TRACKER: u8 = 1 << 0;
+    pub const RELAY: u8 = 1 << 1;
+    pub const DIRECT: u8 = 1 << 2;
+    pub const PUSH: u8 = 1 << 3;
+    pub const RECV: u8 = 1 << 4;
+    pub const CIN: u8 = 1 << 5;
+    pub const PRIVATE: u8 = 1 << 6;
+
     pub fn tracker(&self) -> bool {
-        self.0 & 1 << 0 != 0
+        self.0 & Self::TRACKER != 0
     }
     pub fn relay(&self) -> bool {
-        self.0 & 1 << 1 != 0
+        self.0 & Self::RELAY != 0
     }
     pub fn direct(&self) -> bool {
-        self.0 & 1 << 2 != 0
+        self.0 & Self::DIRECT != 0
     }
     pub fn push(&self) -> bool {
-        self.0 & 1 << 3 != 0
+        self.0 & Self::PUSH != 0
     }
     pub fn recv(&self) -> bool {
-        self.0 & 1 << 4 != 0
+        self.0 & Self::RECV != 0
     }
     pub fn cin(&self) -> bool {
-        self.0 & 1 << 5 != 0
+        self.0 & Self::CIN != 0
     }
     pub fn private(&self) -> bool {
-        self.0 & 1 << 6 != 0
+        self.0 & Self::PRIVATE != 0
     }
 }
 
@@ -74,7 +162,36 @@ impl Debug for Flg1 {
     }
 }
 
-#[derive(PartialEq, serde::Deserialize, serde::Serialize)]
+bitflags::bitflags! {
+    /// Destination groups of a `bcst` atom.
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct BcstGroup: u8 {
+        const ROOT = 1 << 0;
+        const TRACKERS = 1 << 1;
+        const RELAYS = 1 << 2;
+        const ALL = 0xff;
+    }
+}
+
+impl<'a> Deserialize<'a> for BcstGroup {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        u8::deserialize(deserializer).map(Self::from_bits_retain)
+    }
+}
+
+impl Serialize for BcstGroup {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.bits().serialize(serializer)
+    }
+}
+
+#[derive(Clone, PartialEq, serde::Deserialize, serde::Serialize)]
 #[serde(transparent)]
 pub struct VExP(pub [u8; 2]);
 
@@ -84,7 +201,24 @@ impl Debug for VExP {
     }
 }
 
-#[derive(PartialEq)]
+/// `type` of a `pkt` atom, a raw 4 byte identifier.
+#[derive(Clone, Copy, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(transparent)]
+pub struct PktType(pub [u8; 4]);
+
+impl PktType {
+    pub const HEAD: PktType = PktType(*b"head");
+    pub const DATA: PktType = PktType(*b"data");
+    pub const META: PktType = PktType(*b"meta");
+}
+
+impl Debug for PktType {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub struct AtomIpAddr(pub IpAddr);
 
 impl Debug for AtomIpAddr {
@@ -117,6 +251,41 @@ impl From<[u8; 16]> for AtomIpAddr {
     }
 }
 
+/// An `ip` and `port` pair of a grouped atom, e.g. `host`'s `ip\0\0port`.
+#[derive(Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Endpoint {
+    pub ip: AtomIpAddr,
+    pub port: u16,
+}
+
+impl Endpoint {
+    /// Port 0 means the host is firewalled.
+    pub fn is_reachable(&self) -> bool {
+        self.port != 0
+    }
+}
+
+impl Debug for Endpoint {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        Display::fmt(&SocketAddr::from(self.clone()), f)
+    }
+}
+
+impl From<SocketAddr> for Endpoint {
+    fn from(addr: SocketAddr) -> Self {
+        Self {
+            ip: addr.ip().into(),
+            port: addr.port(),
+        }
+    }
+}
+
+impl From<Endpoint> for SocketAddr {
+    fn from(endpoint: Endpoint) -> Self {
+        SocketAddr::new(endpoint.ip.0, endpoint.port)
+    }
+}
+
 impl<'a> Deserialize<'a> for AtomIpAddr {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -154,3 +323,50 @@ impl Serialize for AtomIpAddr {
         vec.serialize(serializer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Id;
+
+    #[test]
+    fn test_id_from_str() {
+        let id: Id = "000102030405060708090a0b0c0d0e0F".parse().unwrap();
+        assert_eq!(id, Id(std::array::from_fn(|i| i as u8)));
+        assert_eq!(id.to_string().parse::<Id>().unwrap(), id);
+        assert!("000102030405060708090a0b0c0d0e0g".parse::<Id>().is_err());
+        assert!("000102030405060708090a0b0c0d0e".parse::<Id>().is_err());
+        assert!("000102030405060708090a0b0c0d0e0\u{e9}"
+            .parse::<Id>()
+            .is_err());
+        assert!("+00102030405060708090a0b0c0d0e0f".parse::<Id>().is_err());
+    }
+
+    #[test]
+    fn test_id_for_channel() {
+        let id = Id::for_channel(&Id([0; 16]), "A", "", 0);
+        assert_eq!(id.to_string(), "41004100410041004100410041004100");
+
+        let bcid = Id(std::array::from_fn(|i| i as u8));
+        let id = Id::for_channel(&bcid, "ab", "x", 500);
+        assert_eq!(id.0[..4], [0xed, 0x97, 0x8e, 0x96]);
+        assert_eq!(id, Id::for_channel(&bcid, "ab", "x", 500 + 256));
+    }
+
+    #[test]
+    fn test_id_for_station_channel() {
+        // expected values from Python's hashlib, not from a running PeerCastStation
+        let bcid = Id(std::array::from_fn(|i| i as u8));
+        let id = Id::for_station_channel(
+            &bcid,
+            "ちゃんねる",
+            "ゲーム",
+            "rtmp://localhost/live/livestream",
+        );
+        assert_eq!(id.to_string(), "410a992582da311cb3f4aa26921ba682");
+        let id = Id::for_station_channel(&Id([0; 16]), "", "", "");
+        assert_eq!(id.to_string(), "fbc84bddeb76d7ba7ce38d2649ccda57");
+        // a length prefix of two bytes
+        let id = Id::for_station_channel(&bcid, &"a".repeat(200), "", "");
+        assert_eq!(id.to_string(), "7df0a27a496eb30255ebc20f63c7334c");
+    }
+}
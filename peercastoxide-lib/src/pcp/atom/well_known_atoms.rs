@@ -1,15 +1,32 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, net::SocketAddr};
 
-use super::values::{AtomIpAddr, Flg1, Id, VExP};
+use super::values::{AtomIpAddr, BcstGroup, Endpoint, Flg1, Id, PktType, VExP};
 
 #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 #[serde(rename = "pcp\n")]
 pub struct Pcp(pub u32);
 
+impl Pcp {
+    pub const IPV4: Pcp = Pcp(1);
+    pub const IPV6: Pcp = Pcp(100);
+
+    pub fn for_addr(addr: &SocketAddr) -> Self {
+        if addr.is_ipv6() {
+            Self::IPV6
+        } else {
+            Self::IPV4
+        }
+    }
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 #[serde(rename = "quit")]
 pub struct Quit(pub u32);
 
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename = "ok")]
+pub struct PcpOk(pub u32);
+
 #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 #[serde(rename = "helo")]
 pub struct Helo {
@@ -31,7 +48,7 @@ pub struct Oleh {
     pub port: Option<u16>,
 }
 
-#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 pub struct Info {
     pub name: String,
     pub bitr: Option<u32>,
@@ -44,7 +61,7 @@ pub struct Info {
     pub sext: Option<String>,
 }
 
-#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 pub struct Trck {
     pub titl: String,
     pub crea: String,
@@ -53,7 +70,7 @@ pub struct Trck {
     pub gnre: Option<String>,
 }
 
-#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 pub struct Chan {
     pub id: Id,
     pub bcid: Id,
@@ -61,12 +78,33 @@ pub struct Chan {
     pub trck: Trck,
 }
 
-#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Pkt {
+    pub r#type: PktType,
+    pub pos: u32,
+    pub data: Vec<u8>,
+    pub cont: Option<u8>,
+}
+
+/// `chan` atom sent on a channel stream, carrying either the channel information or a packet.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename = "chan")]
+pub struct StreamChan {
+    pub id: Id,
+    pub bcid: Option<Id>,
+    pub info: Option<Info>,
+    pub trck: Option<Trck>,
+    pub pkt: Option<Pkt>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename = "host")]
 pub struct Host {
     pub cid: Id,
     pub id: Id,
+    /// The global and the local address of each address family, in that order.
     #[serde(rename = "ip\0\0port")]
-    pub ip_port: Vec<(AtomIpAddr, u16)>,
+    pub ip_port: Vec<Endpoint>,
     pub numl: u32,
     pub numr: u32,
     pub uptm: u32,
@@ -82,18 +120,110 @@ pub struct Host {
     pub uphp: Option<u32>,
 }
 
-#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+impl Host {
+    fn endpoints_of(&self, ipv6: bool) -> impl Iterator<Item = &Endpoint> {
+        self.ip_port
+            .iter()
+            .filter(move |x| x.ip.0.is_ipv6() == ipv6)
+    }
+
+    pub fn global(&self) -> Option<SocketAddr> {
+        self.global_v4().or_else(|| self.global_v6())
+    }
+
+    pub fn global_v4(&self) -> Option<SocketAddr> {
+        self.endpoints_of(false).next().cloned().map(Into::into)
+    }
+
+    pub fn local_v4(&self) -> Option<SocketAddr> {
+        self.endpoints_of(false).nth(1).cloned().map(Into::into)
+    }
+
+    pub fn global_v6(&self) -> Option<SocketAddr> {
+        self.endpoints_of(true).next().cloned().map(Into::into)
+    }
+
+    pub fn local_v6(&self) -> Option<SocketAddr> {
+        self.endpoints_of(true).nth(1).cloned().map(Into::into)
+    }
+
+    /// Adds an address verified by the handshake as the global one of its family, unless the host
+    /// already reports one of that family.
+    pub fn add_verified_addr(&mut self, addr: SocketAddr) {
+        if addr.port() == 0 || self.endpoints_of(addr.is_ipv6()).next().is_some() {
+            return;
+        }
+        self.ip_port.insert(0, addr.into());
+    }
+
+    /// Addresses which can be connected to, global ones first.
+    pub fn reachable_addrs(&self) -> Vec<SocketAddr> {
+        [
+            self.global_v4(),
+            self.global_v6(),
+            self.local_v4(),
+            self.local_v6(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|x| x.port() != 0)
+        .collect()
+    }
+
+    /// Firewalled hosts can only be reached with `push`.
+    pub fn is_firewalled(&self) -> bool {
+        self.flg1.push() || self.global().map(|x| x.port() == 0).unwrap_or(true)
+    }
+}
+
+/// Asks a firewalled host to connect back to `ip`:`port` with `GIV /<cid>`.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename = "push")]
+pub struct Push {
+    pub ip: AtomIpAddr,
+    pub port: u16,
+    pub cid: Id,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 #[serde(rename = "bcst")]
 pub struct Bcst {
-    pub grp: u8,
+    pub grp: BcstGroup,
     pub hops: u8,
     pub ttl: u8,
     pub from: Id,
+    pub dest: Option<Id>,
     pub vers: u32,
     pub vrvp: u32,
     pub vexp: VExP,
     pub vexn: u16,
     pub cid: Option<Id>,
-    pub chan: Chan,
-    pub host: Host,
+    pub chan: Option<Chan>,
+    pub host: Option<Host>,
+    pub push: Option<Push>,
+}
+
+impl Bcst {
+    /// Whether this broadcast should be delivered to the peer `peer_id` belonging to `peer_group`.
+    pub fn is_deliverable_to(&self, peer_id: &Id, peer_group: BcstGroup) -> bool {
+        if &self.from == peer_id {
+            return false;
+        }
+        if let Some(dest) = &self.dest {
+            return dest == peer_id;
+        }
+        self.grp.intersects(peer_group)
+    }
+
+    /// Returns the copy to relay to the next hop, or `None` if the TTL has expired.
+    pub fn next_hop(&self) -> Option<Bcst> {
+        if self.ttl <= 1 {
+            return None;
+        }
+        Some(Bcst {
+            hops: self.hops.saturating_add(1),
+            ttl: self.ttl - 1,
+            ..self.clone()
+        })
+    }
 }
@@ -13,10 +13,16 @@ use tracing::debug;
 
 use crate::pcp::atom::{
     atom_stream::{AtomStreamReader, AtomStreamWriter},
+    from_unknown,
     values::Id,
-    well_known_atoms::{Helo, Oleh, Pcp, Quit},
+    well_known_atoms::{Helo, Host, Oleh, Pcp, Quit},
+    well_known_identifiers::{HOST, QUIT},
 };
 
+pub const QUIT_ERROR: u32 = 1000;
+/// `quit` sent by a servent without free relay slots.
+pub const QUIT_UNAVAILABLE: u32 = QUIT_ERROR + 3;
+
 async fn leave_connection(
     mut reader: AtomStreamReader<impl AsyncRead + Unpin + Send + Sync + 'static>,
 ) -> Result<()> {
@@ -43,12 +49,7 @@ async fn ping(peer_addr: &SocketAddr, session_id: &Id, peer_session_id: &Id) ->
     let mut reader = AtomStreamReader::new(reader);
     let mut writer = AtomStreamWriter::new(writer);
 
-    let pcp = if peer_addr.is_ipv6() {
-        Pcp(100)
-    } else {
-        Pcp(1)
-    };
-    writer.write_atom(&pcp).await?;
+    writer.write_atom(&Pcp::for_addr(peer_addr)).await?;
 
     let helo = Helo {
         sid: session_id.clone(),
@@ -65,27 +66,66 @@ async fn ping(peer_addr: &SocketAddr, session_id: &Id, peer_session_id: &Id) ->
         bail!("session id mismatch")
     }
 
-    writer.write_atom(&Quit(1000)).await?;
-
     tracing::trace!("ping succeeded: {}", peer_addr);
 
+    // `oleh` is enough to verify the port even if the peer has already hung up.
+    if let Err(err) = writer.write_atom(&Quit(QUIT_ERROR)).await {
+        debug!("{}", err);
+        return Ok(());
+    }
+
     leave_connection(reader).await?;
     Ok(())
 }
 
+/// What an incoming handshake negotiated with the peer.
+#[derive(Debug)]
+pub struct Handshake {
+    pub helo: Helo,
+    /// The lower of our version and `ver` of the peer's `helo`; ours if it sent none.
+    pub version: u32,
+    /// The peer's address with IPv4-mapped IPv6 addresses of dual-stack sockets unmapped.
+    pub peer_ip: IpAddr,
+    /// The port the peer listens on; 0 when the ping failed.
+    pub peer_port: u16,
+}
+
 pub async fn handshake(
     reader: &mut AtomStreamReader<impl AsyncRead + Unpin + Send + Sync>,
     writer: &mut AtomStreamWriter<impl AsyncWrite + Unpin + Send + Sync>,
     session_id: &Id,
     peer_ip_addr: IpAddr,
     agent_name: &'static str,
+    version: u32,
     ping_timeout: Duration,
-) -> Result<()> {
+) -> Result<Handshake> {
     let pcp: Pcp = reader.read_atom().await?;
-    if pcp.0 != 1 && pcp.0 != 100 {
+    if pcp != Pcp::IPV4 && pcp != Pcp::IPV6 {
         bail!("invalid atom")
     }
+    handshake_incoming(
+        reader,
+        writer,
+        session_id,
+        peer_ip_addr,
+        agent_name,
+        version,
+        ping_timeout,
+    )
+    .await
+}
 
+/// Answers `helo` with `oleh`. Channel streams start here because the HTTP request replaces `pcp\n`.
+pub async fn handshake_incoming(
+    reader: &mut AtomStreamReader<impl AsyncRead + Unpin + Send + Sync>,
+    writer: &mut AtomStreamWriter<impl AsyncWrite + Unpin + Send + Sync>,
+    session_id: &Id,
+    peer_ip_addr: IpAddr,
+    agent_name: &'static str,
+    version: u32,
+    ping_timeout: Duration,
+) -> Result<Handshake> {
+    let peer_ip_addr = peer_ip_addr.to_canonical();
     let helo: Helo = reader.read_atom().await?;
 
     let pinged_port = 'block: {
@@ -108,12 +148,151 @@ pub async fn handshake(
     let oleh = Oleh {
         sid: session_id.clone(),
         agnt: Some(agent_name.into()),
-        ver: Some(1218),
+        ver: Some(version),
         rip: Some(peer_ip_addr.into()),
         port: Some(peer_port),
     };
     writer.write_atom(&oleh).await?;
 
     tracing::trace!("handshake succeeded");
+    Ok(Handshake {
+        version: helo.ver.map_or(version, |x| x.min(version)),
+        helo,
+        peer_ip: peer_ip_addr,
+        peer_port,
+    })
+}
+
+/// Sends `helo` and waits for `oleh` on a connection opened by us.
+pub async fn handshake_outgoing(
+    reader: &mut AtomStreamReader<impl AsyncRead + Unpin + Send + Sync>,
+    writer: &mut AtomStreamWriter<impl AsyncWrite + Unpin + Send + Sync>,
+    helo: &Helo,
+) -> Result<Oleh> {
+    writer.write_atom(helo).await?;
+    let oleh: Oleh = reader.read_atom().await?;
+    tracing::trace!("handshake succeeded: {}", oleh.sid);
+    Ok(oleh)
+}
+
+/// Sends the body of a `503` relay response after the handshake: other hosts of the channel and `quit`.
+pub async fn write_unavailable(
+    writer: &mut AtomStreamWriter<impl AsyncWrite + Unpin + Send + Sync>,
+    hosts: &[Host],
+) -> Result<()> {
+    for host in hosts {
+        writer.write_atom(host).await?;
+    }
+    writer.write_atom(&Quit(QUIT_UNAVAILABLE)).await?;
     Ok(())
 }
+
+/// Reads the hosts of a `503` relay response until `quit`.
+pub async fn read_unavailable(
+    reader: &mut AtomStreamReader<impl AsyncRead + Unpin + Send + Sync>,
+) -> Result<Vec<Host>> {
+    let mut hosts = Vec::new();
+    loop {
+        let atom = reader.read_unknown_atom().await?;
+        match atom.identifier().0.as_ref() {
+            HOST => match from_unknown(atom) {
+                Ok(host) => hosts.push(host),
+                Err(err) => debug!("invalid host: {}", err),
+            },
+            QUIT => return Ok(hosts),
+            _ => tracing::trace!("{}", atom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv6Addr},
+        time::Duration,
+    };
+
+    use tokio::{net::TcpListener, spawn};
+
+    use crate::pcp::atom::{
+        atom_stream::{AtomStreamReader, AtomStreamWriter},
+        values::Id,
+        well_known_atoms::{Helo, Oleh, Pcp},
+    };
+
+    use super::{handshake, handshake_outgoing};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_handshake_ipv6() {
+        let localhost = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        // the peer's servent which answers our ping
+        let peer_listener = TcpListener::bind((localhost, 0)).await.unwrap();
+        let peer_port = peer_listener.local_addr().unwrap().port();
+        spawn(async move {
+            let (stream, _) = peer_listener.accept().await.unwrap();
+            let (reader, writer) = stream.into_split();
+            let mut reader = AtomStreamReader::new(reader);
+            let mut writer = AtomStreamWriter::new(writer);
+            let pcp: Pcp = reader.read_atom().await.unwrap();
+            assert_eq!(pcp, Pcp::IPV6);
+            let _: Helo = reader.read_atom().await.unwrap();
+            let oleh = Oleh {
+                sid: Id([2; 16]),
+                agnt: None,
+                ver: None,
+                rip: None,
+                port: None,
+            };
+            writer.write_atom(&oleh).await.unwrap();
+        });
+
+        let listener = TcpListener::bind((localhost, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = spawn(async move {
+            let (stream, peer_addr) = listener.accept().await.unwrap();
+            let (reader, writer) = stream.into_split();
+            let mut reader = AtomStreamReader::new(reader);
+            let mut writer = AtomStreamWriter::new(writer);
+            handshake(
+                &mut reader,
+                &mut writer,
+                &Id([1; 16]),
+                peer_addr.ip(),
+                "test",
+                1218,
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap()
+        });
+
+        let (reader, writer) = tokio::net::TcpStream::connect(addr)
+            .await
+            .unwrap()
+            .into_split();
+        let mut reader = AtomStreamReader::new(reader);
+        let mut writer = AtomStreamWriter::new(writer);
+        writer.write_atom(&Pcp::IPV6).await.unwrap();
+        let helo = Helo {
+            sid: Id([2; 16]),
+            agnt: Some("peer".into()),
+            ver: Some(1200),
+            port: None,
+            ping: Some(peer_port),
+            bcid: None,
+        };
+        let oleh = handshake_outgoing(&mut reader, &mut writer, &helo)
+            .await
+            .unwrap();
+        assert_eq!(oleh.rip.map(|x| x.0), Some(localhost));
+        assert_eq!(oleh.port, Some(peer_port));
+        assert_eq!(oleh.ver, Some(1218));
+        assert_eq!(oleh.agnt.as_deref(), Some("test"));
+
+        let handshake = server.await.unwrap();
+        assert_eq!(handshake.version, 1200);
+        assert_eq!(handshake.helo.agnt.as_deref(), Some("peer"));
+        assert_eq!(handshake.peer_ip, localhost);
+        assert_eq!(handshake.peer_port, peer_port);
+    }
+}
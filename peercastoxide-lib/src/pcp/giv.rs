@@ -0,0 +1,73 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::pcp::atom::values::Id;
+
+/// `GIV /<channel-id>` sent by a firewalled host which received a `push` atom.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Giv {
+    pub channel_id: Id,
+}
+
+impl FromStr for Giv {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let Some(rest) = line.strip_prefix("GIV ") else {
+            bail!("not a GIV request: {:?}", line);
+        };
+        // PeerCast reads the channel id after the first slash
+        let (_, channel_id) = rest
+            .split_once('/')
+            .ok_or_else(|| anyhow!("channel id missing: {:?}", line))?;
+        Ok(Self {
+            channel_id: channel_id.trim().parse()?,
+        })
+    }
+}
+
+impl Display for Giv {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "GIV /{}\r\n\r\n", self.channel_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pcp::atom::values::Id;
+
+    use super::Giv;
+
+    #[test]
+    fn test_giv() {
+        let giv = Giv {
+            channel_id: Id(std::array::from_fn(|i| i as u8 * 17)),
+        };
+        let line = giv.to_string();
+        assert_eq!(line, "GIV /00112233445566778899aabbccddeeff\r\n\r\n");
+        assert_eq!(line.parse::<Giv>().unwrap(), giv);
+        assert_eq!(
+            "GIV 0123/00112233445566778899AABBCCDDEEFF\r\n"
+                .parse::<Giv>()
+                .unwrap(),
+            giv
+        );
+
+        assert!("GIV /00112233445566778899aabbccddeexx"
+            .parse::<Giv>()
+            .is_err());
+        assert!("GIV /0011".parse::<Giv>().is_err());
+        assert!("GIV 00112233445566778899aabbccddeeff"
+            .parse::<Giv>()
+            .is_err());
+        assert!("GIV /".parse::<Giv>().is_err());
+        assert!("GET /00112233445566778899aabbccddeeff"
+            .parse::<Giv>()
+            .is_err());
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Result};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+use crate::pcp::atom::values::Id;
+
+pub const PCP_CONTENT_TYPE: &str = "application/x-peercast-pcp";
+
+/// Characters of a tip to encode in a query, e.g. the brackets of `[::1]:7144`
+const TIP: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'&')
+    .add(b'+')
+    .add(b'<')
+    .add(b'>')
+    .add(b'[')
+    .add(b']');
+
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+
+#[derive(Debug)]
+pub struct RequestHead {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+async fn read_line(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<String> {
+    let mut buf = Vec::new();
+    let len = (&mut *reader)
+        .take(MAX_LINE_LENGTH)
+        .read_until(b'\n', &mut buf)
+        .await?;
+    if len == 0 {
+        bail!("connection closed");
+    }
+    if buf.last() != Some(&b'\n') {
+        bail!("line too long");
+    }
+    let line = String::from_utf8(buf)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+async fn read_headers(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<Vec<(String, String)>> {
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader).await?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if headers.len() >= MAX_HEADERS {
+            bail!("too many headers");
+        }
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid header: {:?}", line))?;
+        headers.push((key.trim().to_owned(), value.trim().to_owned()));
+    }
+}
+
+pub async fn read_request_head(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<RequestHead> {
+    let request_line = read_line(reader).await?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(path), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        bail!("invalid request line: {:?}", request_line);
+    };
+    Ok(RequestHead {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        headers: read_headers(reader).await?,
+    })
+}
+
+/// Reads the status line and headers, and returns the status code.
+pub async fn read_response_head(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> Result<(u16, Vec<(String, String)>)> {
+    let status_line = read_line(reader).await?;
+    let status = status_line
+        .split(' ')
+        .nth(1)
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| anyhow!("invalid status line: {:?}", status_line))?;
+    Ok((status, read_headers(reader).await?))
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChannelRequestKind {
+    /// `/channel/<id>`: relay over PCP, or the raw stream without `x-peercast-pcp`
+    Channel,
+    /// `/stream/<id>`
+    Stream,
+    /// `/pls/<id>`
+    Pls,
+}
+
+impl ChannelRequestKind {
+    fn prefix(&self) -> &'static str {
+        match self {
+            Self::Channel => "/channel/",
+            Self::Stream => "/stream/",
+            Self::Pls => "/pls/",
+        }
+    }
+}
+
+/// `Range: bytes=<start>-[<end>]`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl FromStr for ByteRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .trim()
+            .strip_prefix("bytes=")
+            .and_then(|x| x.split_once('-'))
+            .ok_or_else(|| anyhow!("unsupported range: {:?}", s))?;
+        Ok(Self {
+            start: start.trim().parse()?,
+            end: match end.trim() {
+                "" => None,
+                end => Some(end.parse()?),
+            },
+        })
+    }
+}
+
+impl Display for ByteRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bytes={}-", self.start)?;
+        self.end.iter().try_for_each(|end| write!(f, "{}", end))
+    }
+}
+
+/// `tip` as the value of a query parameter.
+pub fn encode_tip(tip: &str) -> String {
+    utf8_percent_encode(tip, TIP).to_string()
+}
+
+/// A `GET` for a channel, shared by servents and proxies.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChannelRequest {
+    pub kind: ChannelRequestKind,
+    pub channel_id: Id,
+    /// Extension after the channel ID without the dot, e.g. `flv`
+    pub ext: Option<String>,
+    /// `tip=<host:port>` of a host which relays the channel, percent-decoded
+    pub tip: Option<String>,
+    /// `x-peercast-pcp`
+    pub pcp: Option<u32>,
+    /// `x-peercast-pos`, the stream position to resume from
+    pub pos: Option<u32>,
+    /// `x-peercast-port`, the listening port of the client
+    pub port: Option<u16>,
+    pub range: Option<ByteRange>,
+}
+
+impl ChannelRequest {
+    pub fn new(kind: ChannelRequestKind, channel_id: Id) -> Self {
+        Self {
+            kind,
+            channel_id,
+            ext: None,
+            tip: None,
+            pcp: None,
+            pos: None,
+            port: None,
+            range: None,
+        }
+    }
+
+    /// Parses the path and query, e.g. `/pls/<id>.m3u?tip=<host:port>`.
+    pub fn from_target(target: &str) -> Option<Self> {
+        let (kind, rest) = [
+            ChannelRequestKind::Channel,
+            ChannelRequestKind::Stream,
+            ChannelRequestKind::Pls,
+        ]
+        .into_iter()
+        .find_map(|kind| Some((kind, target.strip_prefix(kind.prefix())?)))?;
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (channel_id, ext) = match path.split_once('.') {
+            Some((channel_id, ext)) => (channel_id, Some(ext.to_owned())),
+            None => (path, None),
+        };
+        let mut req = Self::new(kind, channel_id.parse().ok()?);
+        req.ext = ext.filter(|x| !x.is_empty());
+        req.tip = query
+            .split('&')
+            .find_map(|param| param.strip_prefix("tip="))
+            .filter(|x| !x.is_empty())
+            .and_then(|x| Some(percent_decode_str(x).decode_utf8().ok()?.into_owned()));
+        Some(req)
+    }
+
+    /// Returns `None` for requests which are not for a channel.
+    pub fn from_head(head: &RequestHead) -> Result<Option<Self>> {
+        if head.method != "GET" {
+            return Ok(None);
+        }
+        let Some(mut req) = Self::from_target(&head.path) else {
+            return Ok(None);
+        };
+        req.pcp = head.header("x-peercast-pcp").map(str::parse).transpose()?;
+        req.pos = head.header("x-peercast-pos").map(str::parse).transpose()?;
+        req.port = head.header("x-peercast-port").map(str::parse).transpose()?;
+        req.range = head.header("Range").map(str::parse).transpose()?;
+        Ok(Some(req))
+    }
+
+    /// A relay request to receive the channel over PCP.
+    pub fn is_relay(&self) -> bool {
+        self.kind == ChannelRequestKind::Channel && self.pcp.is_some()
+    }
+
+    pub fn target(&self) -> String {
+        let mut target = format!("{}{}", self.kind.prefix(), self.channel_id);
+        if let Some(ext) = &self.ext {
+            target.push('.');
+            target.push_str(ext);
+        }
+        if let Some(tip) = &self.tip {
+            target.push_str("?tip=");
+            target.push_str(&encode_tip(tip));
+        }
+        target
+    }
+
+    pub fn to_request(&self, user_agent: &str) -> String {
+        let mut request = format!(
+            "GET {} HTTP/1.0\r\nUser-Agent: {}\r\n",
+            self.target(),
+            user_agent
+        );
+        let headers = [
+            ("x-peercast-pcp", self.pcp.map(|x| x.to_string())),
+            ("x-peercast-pos", self.pos.map(|x| x.to_string())),
+            ("x-peercast-port", self.port.map(|x| x.to_string())),
+            ("Range", self.range.map(|x| x.to_string())),
+        ];
+        for (key, value) in headers {
+            if let Some(value) = value {
+                request.push_str(&format!("{}: {}\r\n", key, value));
+            }
+        }
+        request.push_str("\r\n");
+        request
+    }
+}
+
+/// A response head without a body, e.g. `404 Not Found`.
+pub fn status_response(server: &str, status: &str) -> String {
+    format!("HTTP/1.0 {}\r\nServer: {}\r\n\r\n", status, server)
+}
+
+/// `200 OK` followed by the stream of `content_type`.
+pub fn ok_response(server: &str, content_type: &str) -> String {
+    format!(
+        "HTTP/1.0 200 OK\r\nServer: {}\r\nContent-Type: {}\r\n\r\n",
+        server, content_type
+    )
+}
+
+/// `200 OK` to a relay request, followed by the PCP handshake.
+pub fn pcp_ok_response(server: &str) -> String {
+    ok_response(server, PCP_CONTENT_TYPE)
+}
+
+/// `503` to a full relay; PeerCast continues with the handshake and a list of `host` atoms.
+pub fn pcp_unavailable_response(server: &str) -> String {
+    format!(
+        "HTTP/1.0 503 Service Unavailable\r\nServer: {}\r\nContent-Type: {}\r\n\r\n",
+        server, PCP_CONTENT_TYPE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pcp::atom::values::Id;
+
+    use super::{ByteRange, ChannelRequest, ChannelRequestKind, RequestHead};
+
+    #[test]
+    fn test_channel_request() {
+        let head = RequestHead {
+            method: "GET".into(),
+            path: "/channel/0123456789abcdef0123456789abcdef".into(),
+            headers: [
+                ("User-Agent", "PeerCastStation/2.0"),
+                ("X-PeerCast-PCP", "1"),
+                ("x-peercast-pos", "4096"),
+                ("x-peercast-port", "7144"),
+            ]
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .to_vec(),
+        };
+        let req = ChannelRequest::from_head(&head).unwrap().unwrap();
+        assert!(req.is_relay());
+        assert_eq!(req.pos, Some(4096));
+        assert_eq!(req.port, Some(7144));
+        assert_eq!(
+            req.to_request("PeerCastOxide"),
+            concat!(
+                "GET /channel/0123456789abcdef0123456789abcdef HTTP/1.0\r\n",
+                "User-Agent: PeerCastOxide\r\n",
+                "x-peercast-pcp: 1\r\n",
+                "x-peercast-pos: 4096\r\n",
+                "x-peercast-port: 7144\r\n",
+                "\r\n",
+            )
+        );
+
+        let req = ChannelRequest::from_target(
+            "/pls/0123456789abcdef0123456789abcdef.m3u?tip=192.168.0.1:7144",
+        )
+        .unwrap();
+        assert_eq!(req.kind, ChannelRequestKind::Pls);
+        assert_eq!(
+            req.channel_id,
+            "0123456789abcdef0123456789abcdef".parse::<Id>().unwrap()
+        );
+        assert_eq!(req.ext.as_deref(), Some("m3u"));
+        assert_eq!(req.tip.as_deref(), Some("192.168.0.1:7144"));
+        assert_eq!(
+            req.target(),
+            "/pls/0123456789abcdef0123456789abcdef.m3u?tip=192.168.0.1:7144"
+        );
+        assert!(!req.is_relay());
+        assert!(ChannelRequest::from_target("/admin?cmd=viewxml").is_none());
+
+        let target = "/stream/0123456789abcdef0123456789abcdef.flv?tip=%5B::1%5D:7144";
+        let req = ChannelRequest::from_target(target).unwrap();
+        assert_eq!(req.tip.as_deref(), Some("[::1]:7144"));
+        assert_eq!(req.target(), target);
+        let req = ChannelRequest::from_target(
+            "/channel/0123456789abcdef0123456789abcdef?tip=%5B%3A%3A1%5D%3A7144",
+        )
+        .unwrap();
+        assert_eq!(req.tip.as_deref(), Some("[::1]:7144"));
+
+        let range: ByteRange = "bytes=100-".parse().unwrap();
+        assert_eq!(
+            range,
+            ByteRange {
+                start: 100,
+                end: None
+            }
+        );
+        assert_eq!(range.to_string(), "bytes=100-");
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    net::SocketAddr,
+};
+
+use crate::pcp::atom::well_known_atoms::Host;
+
+/// Upstream hosts to try in order, extended with the alternatives sent in `503` responses.
+#[derive(Debug)]
+pub struct RelayCandidates {
+    queue: VecDeque<SocketAddr>,
+    tried: HashSet<SocketAddr>,
+    remaining_attempts: usize,
+}
+
+impl RelayCandidates {
+    pub fn new(addrs: impl IntoIterator<Item = SocketAddr>, max_attempts: usize) -> Self {
+        Self {
+            queue: addrs.into_iter().collect(),
+            tried: HashSet::new(),
+            remaining_attempts: max_attempts,
+        }
+    }
+
+    /// Queues the reachable hosts with a free relay slot, least loaded first.
+    pub fn add_hosts(&mut self, hosts: &[Host]) {
+        let mut hosts = hosts
+            .iter()
+            .filter(|host| host.flg1.relay() && !host.is_firewalled())
+            .collect::<Vec<_>>();
+        hosts.sort_by_key(|host| host.numr);
+        let addrs = hosts.into_iter().flat_map(|host| host.reachable_addrs());
+        for addr in addrs {
+            if !self.tried.contains(&addr) && !self.queue.contains(&addr) {
+                self.queue.push_back(addr);
+            }
+        }
+    }
+}
+
+impl Iterator for RelayCandidates {
+    type Item = SocketAddr;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining_attempts == 0 {
+            return None;
+        }
+        let addr = self.queue.pop_front()?;
+        self.remaining_attempts -= 1;
+        self.tried.insert(addr);
+        Some(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use crate::pcp::atom::{
+        values::{Flg1, Id, VExP},
+        well_known_atoms::Host,
+    };
+
+    use super::RelayCandidates;
+
+    fn host(last_octet: u8, numr: u32, flg1: u8) -> Host {
+        Host {
+            cid: Id([1; 16]),
+            id: Id([last_octet; 16]),
+            ip_port: vec![
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last_octet)), 7144).into(),
+            ],
+            numl: 0,
+            numr,
+            uptm: 0,
+            ver: 1218,
+            vevp: 27,
+            vexp: VExP(*b"OX"),
+            vexn: 1,
+            flg1: Flg1(flg1),
+            oldp: None,
+            newp: None,
+            upip: None,
+            uppt: None,
+            uphp: None,
+        }
+    }
+
+    #[test]
+    fn test_walk_alternatives() {
+        let tracker: SocketAddr = "10.0.0.1:7144".parse().unwrap();
+        let mut candidates = RelayCandidates::new([tracker], 3);
+        assert_eq!(candidates.next(), Some(tracker));
+        candidates.add_hosts(&[
+            host(1, 0, 0b10), // already tried
+            host(2, 4, 0b10),
+            host(3, 1, 0b10),
+            host(4, 0, 0b00),   // no relay slot
+            host(5, 0, 0b1010), // firewalled
+            host(6, 2, 0b10),
+        ]);
+        let addrs = candidates.collect::<Vec<_>>();
+        assert_eq!(
+            addrs,
+            ["10.0.0.3:7144", "10.0.0.6:7144"].map(|x| x.parse::<SocketAddr>().unwrap())
+        );
+    }
+}
@@ -175,6 +175,24 @@ pub struct ChannelsFound {
     pub channel: Vec<Channel>,
 }
 
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct HostCacheEntry {
+    #[serde(rename = "@ip")]
+    pub ip: Option<SocketAddr>,
+    /// `SERVENT` or `TRACKER`
+    #[serde(rename = "@type")]
+    pub r#type: String,
+    /// Unix time when the host was seen
+    #[serde(rename = "@time")]
+    pub time: u64,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct HostCache {
+    #[serde(default)]
+    pub host: Vec<HostCacheEntry>,
+}
+
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct Peercast {
     /// PeerCastStation only
@@ -187,5 +205,5 @@ pub struct Peercast {
     pub channels_relayed: ChannelsRelayed,
     pub channels_found: ChannelsFound,
     /// Not on PeerCastStation
-    pub host_cache: Option<()>,
+    pub host_cache: Option<HostCache>,
 }
@@ -0,0 +1,200 @@
+use std::net::Ipv6Addr;
+
+use crate::pcp::{
+    atom::{values::Id, well_known_atoms::Info},
+    http::encode_tip,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PlaylistFormat {
+    Pls,
+    M3u,
+    Asx,
+}
+
+impl PlaylistFormat {
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.trim_start_matches('.').to_ascii_lowercase().as_str() {
+            "pls" => Some(Self::Pls),
+            "m3u" | "m3u8" => Some(Self::M3u),
+            "asx" => Some(Self::Asx),
+            _ => None,
+        }
+    }
+
+    /// Windows Media streams need ASX, everything else plays from PLS.
+    pub fn for_stream_type(r#type: Option<&str>) -> Self {
+        match r#type.map(|x| x.to_ascii_uppercase()).as_deref() {
+            Some("WMV" | "WMA" | "ASF") => Self::Asx,
+            _ => Self::Pls,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Pls => "audio/x-scpls",
+            Self::M3u => "audio/x-mpegurl",
+            Self::Asx => "video/x-ms-asf",
+        }
+    }
+}
+
+/// Extension of the stream, e.g. `.flv`, taken from `sext` or derived from `type`.
+pub fn stream_extension(info: &Info) -> String {
+    if let Some(sext) = info.sext.as_deref().filter(|x| !x.is_empty()) {
+        return format!(".{}", sext.trim_start_matches('.'));
+    }
+    match info.r#type.as_deref().filter(|x| !x.is_empty()) {
+        Some(r#type) => format!(".{}", r#type.to_ascii_lowercase()),
+        None => "".into(),
+    }
+}
+
+/// Whether a `Host` header is a plain `host[:port]`, safe to put into a URL.
+pub fn is_authority(host: &str) -> bool {
+    let (name, port) = match host.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((ip, port)) if ip.parse::<Ipv6Addr>().is_ok() => ("", port),
+            _ => return false,
+        },
+        None => match host.find(':') {
+            Some(i) if i > 0 => host.split_at(i),
+            Some(_) => return false,
+            None if !host.is_empty() => (host, ""),
+            None => return false,
+        },
+    };
+    let name_ok = name
+        .bytes()
+        .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'.');
+    let port_ok = port.is_empty()
+        || port.strip_prefix(':').is_some_and(|port| {
+            port.bytes().all(|c| c.is_ascii_digit()) && port.parse::<u16>().is_ok()
+        });
+    name_ok && port_ok
+}
+
+/// `http://<authority>/stream/<id><ext>?tip=<tip>`
+pub fn stream_url(authority: &str, channel_id: &Id, ext: &str, tip: Option<&str>) -> String {
+    let mut url = format!("http://{}/stream/{}{}", authority, channel_id, ext);
+    if let Some(tip) = tip.filter(|x| !x.is_empty()) {
+        url.push_str("?tip=");
+        url.push_str(&encode_tip(tip));
+    }
+    url
+}
+
+fn escape_xml(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '&' => "&amp;".into(),
+            '<' => "&lt;".into(),
+            '>' => "&gt;".into(),
+            '"' => "&quot;".into(),
+            '\'' => "&apos;".into(),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+pub fn create_playlist(format: PlaylistFormat, title: &str, url: &str) -> String {
+    // players ignore unknown lines, but a newline in the title would break the entry
+    let title = title.replace(['\r', '\n'], " ");
+    match format {
+        PlaylistFormat::Pls => format!(
+            "[playlist]\r\nNumberOfEntries=1\r\nFile1={}\r\nTitle1={}\r\nLength1=-1\r\nVersion=2\r\n",
+            url, title
+        ),
+        PlaylistFormat::M3u => format!("#EXTM3U\r\n#EXTINF:-1,{}\r\n{}\r\n", title, url),
+        PlaylistFormat::Asx => format!(
+            "<ASX Version=\"3.0\">\r\n<TITLE>{0}</TITLE>\r\n<ENTRY>\r\n<TITLE>{0}</TITLE>\r\n<REF href=\"{1}\" />\r\n</ENTRY>\r\n</ASX>\r\n",
+            escape_xml(&title),
+            escape_xml(url)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pcp::atom::{values::Id, well_known_atoms::Info};
+
+    use super::{create_playlist, is_authority, stream_extension, stream_url, PlaylistFormat};
+
+    #[test]
+    fn test_is_authority() {
+        for host in [
+            "localhost",
+            "localhost:7144",
+            "192.168.0.1:7144",
+            "[::1]:7144",
+            "[::1]",
+        ] {
+            assert!(is_authority(host), "{}", host);
+        }
+        for host in [
+            "",
+            ":7144",
+            "localhost:",
+            "localhost:+80",
+            "localhost:65536",
+            "[::1",
+            "[localhost]:7144",
+            "evil.example/x?",
+            "a@evil.example",
+            "localhost\r\nX-Injected: 1",
+        ] {
+            assert!(!is_authority(host), "{}", host);
+        }
+    }
+
+    #[test]
+    fn test_playlists() {
+        let info = Info {
+            name: "a & b".into(),
+            bitr: None,
+            gnre: "".into(),
+            url: "".into(),
+            desc: "".into(),
+            cmnt: "".into(),
+            r#type: Some("WMV".into()),
+            styp: None,
+            sext: None,
+        };
+        let url = stream_url(
+            "127.0.0.1:7144",
+            &Id([0xab; 16]),
+            &stream_extension(&info),
+            Some("192.168.0.1:7144"),
+        );
+        assert_eq!(
+            url,
+            "http://127.0.0.1:7144/stream/abababababababababababababababab.wmv?tip=192.168.0.1:7144"
+        );
+        let format = PlaylistFormat::for_stream_type(info.r#type.as_deref());
+        assert_eq!(format, PlaylistFormat::Asx);
+        assert_eq!(
+            create_playlist(format, &info.name, &url),
+            concat!(
+                "<ASX Version=\"3.0\">\r\n",
+                "<TITLE>a &amp; b</TITLE>\r\n",
+                "<ENTRY>\r\n",
+                "<TITLE>a &amp; b</TITLE>\r\n",
+                "<REF href=\"http://127.0.0.1:7144/stream/abababababababababababababababab.wmv?tip=192.168.0.1:7144\" />\r\n",
+                "</ENTRY>\r\n",
+                "</ASX>\r\n",
+            )
+        );
+        assert_eq!(
+            create_playlist(PlaylistFormat::M3u, "name", "http://host/stream"),
+            "#EXTM3U\r\n#EXTINF:-1,name\r\nhttp://host/stream\r\n"
+        );
+        assert_eq!(
+            stream_url("[::1]:7144", &Id([0xab; 16]), ".flv", Some("[::1]:7145")),
+            "http://[::1]:7144/stream/abababababababababababababababab.flv?tip=%5B::1%5D:7145"
+        );
+        assert_eq!(
+            PlaylistFormat::from_extension(".pls"),
+            Some(PlaylistFormat::Pls)
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use peercastoxide_lib::pcp::atom::{
+    values::Id,
+    well_known_atoms::{Host, Info, Pkt, Trck},
+};
+use tokio::sync::broadcast;
+
+/// Number of data packets kept for connections which join in the middle of the stream.
+const CONTENT_BUFFER_PACKETS: usize = 64;
+const EVENT_QUEUE_SIZE: usize = 256;
+
+#[derive(Clone, Debug)]
+pub enum ChannelEvent {
+    Info(Arc<(Info, Trck)>),
+    Head(Arc<Pkt>),
+    Data(Arc<Pkt>),
+}
+
+/// What a new connection has to send before following the live events.
+pub struct Subscription {
+    pub info: Option<Arc<(Info, Trck)>>,
+    pub head: Option<Arc<Pkt>>,
+    pub packets: Vec<Arc<Pkt>>,
+    pub receiver: broadcast::Receiver<ChannelEvent>,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    broadcast_id: Option<Id>,
+    info: Option<Arc<(Info, Trck)>>,
+    head: Option<Arc<Pkt>>,
+    packets: VecDeque<Arc<Pkt>>,
+    relays: u32,
+    listeners: u32,
+    /// Latest `host` reported by each directly connected relay.
+    relay_hosts: HashMap<Id, Host>,
+}
+
+/// A channel received from upstream with a ring buffer of its recent content.
+#[derive(getset::Getters)]
+pub struct Channel {
+    #[get = "pub"]
+    id: Id,
+    #[get = "pub"]
+    started_at: Instant,
+    state: RwLock<ChannelState>,
+    sender: broadcast::Sender<ChannelEvent>,
+}
+
+impl Channel {
+    pub fn new(id: Id) -> Self {
+        Self {
+            id,
+            started_at: Instant::now(),
+            state: Default::default(),
+            sender: broadcast::channel(EVENT_QUEUE_SIZE).0,
+        }
+    }
+
+    pub fn broadcast_id(&self) -> Option<Id> {
+        self.state.read().unwrap().broadcast_id.clone()
+    }
+
+    pub fn set_broadcast_id(&self, broadcast_id: Id) {
+        self.state.write().unwrap().broadcast_id = Some(broadcast_id);
+    }
+
+    pub fn info(&self) -> Option<Arc<(Info, Trck)>> {
+        self.state.read().unwrap().info.clone()
+    }
+
+    pub fn is_receiving(&self) -> bool {
+        self.state.read().unwrap().head.is_some()
+    }
+
+    pub fn set_info(&self, info: Info, trck: Trck) {
+        let info = Arc::new((info, trck));
+        let mut state = self.state.write().unwrap();
+        state.info = Some(info.clone());
+        let _ = self.sender.send(ChannelEvent::Info(info));
+    }
+
+    /// A new head starts a new stream, so the buffered packets are discarded.
+    pub fn set_head(&self, pkt: Pkt) {
+        let pkt = Arc::new(pkt);
+        let mut state = self.state.write().unwrap();
+        state.head = Some(pkt.clone());
+        state.packets.clear();
+        let _ = self.sender.send(ChannelEvent::Head(pkt));
+    }
+
+    pub fn push_data(&self, pkt: Pkt) {
+        let pkt = Arc::new(pkt);
+        let mut state = self.state.write().unwrap();
+        if state.packets.len() >= CONTENT_BUFFER_PACKETS {
+            state.packets.pop_front();
+        }
+        state.packets.push_back(pkt.clone());
+        let _ = self.sender.send(ChannelEvent::Data(pkt));
+    }
+
+    pub fn subscribe(&self) -> Subscription {
+        // Subscribe while holding the lock so that no packet falls between the snapshot and the receiver.
+        let state = self.state.read().unwrap();
+        Subscription {
+            info: state.info.clone(),
+            head: state.head.clone(),
+            packets: state.packets.iter().cloned().collect(),
+            receiver: self.sender.subscribe(),
+        }
+    }
+
+    pub fn relays(&self) -> u32 {
+        self.state.read().unwrap().relays
+    }
+
+    pub fn listeners(&self) -> u32 {
+        self.state.read().unwrap().listeners
+    }
+
+    pub fn relay_hosts(&self) -> Vec<Host> {
+        self.state
+            .read()
+            .unwrap()
+            .relay_hosts
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    pub fn set_relay_host(&self, session_id: Id, host: Host) {
+        let mut state = self.state.write().unwrap();
+        state.relay_hosts.insert(session_id, host);
+    }
+
+    pub fn remove_relay_host(&self, session_id: &Id) {
+        self.state.write().unwrap().relay_hosts.remove(session_id);
+    }
+
+    pub fn try_add_relay(self: &Arc<Self>, max_relays: u32) -> Option<ConnectionSlot> {
+        let mut state = self.state.write().unwrap();
+        if state.relays >= max_relays {
+            return None;
+        }
+        state.relays += 1;
+        Some(ConnectionSlot(self.clone(), SlotKind::Relay))
+    }
+
+    pub fn try_add_listener(self: &Arc<Self>, max_listeners: u32) -> Option<ConnectionSlot> {
+        let mut state = self.state.write().unwrap();
+        if state.listeners >= max_listeners {
+            return None;
+        }
+        state.listeners += 1;
+        Some(ConnectionSlot(self.clone(), SlotKind::Listener))
+    }
+}
+
+enum SlotKind {
+    Relay,
+    Listener,
+}
+
+/// Counts a downstream connection until dropped.
+pub struct ConnectionSlot(Arc<Channel>, SlotKind);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut state = self.0.state.write().unwrap();
+        match self.1 {
+            SlotKind::Relay => state.relays -= 1,
+            SlotKind::Listener => state.listeners -= 1,
+        }
+    }
+}
@@ -0,0 +1,57 @@
+mod channel;
+mod playlist;
+mod servent;
+mod stream;
+mod upstream;
+
+use std::{num::NonZeroU16, sync::Arc};
+
+use clap::Parser;
+use futures::future::select_all;
+use peercastoxide_lib::{net::bind_dual_stack, pcp::atom::values::Id};
+use tokio::{spawn, sync::mpsc};
+
+use crate::{channel::Channel, servent::Servent};
+
+const UPSTREAM_QUEUE_SIZE: usize = 64;
+
+#[derive(Debug, clap::Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Tracker or relay to receive the channel from, e.g. `127.0.0.1:7144`
+    #[arg(long)]
+    upstream: String,
+    #[arg(long)]
+    channel_id: Id,
+    #[arg(long, default_value_t = NonZeroU16::new(7145).unwrap())]
+    port: NonZeroU16,
+    #[arg(long, default_value_t = 8)]
+    max_relays: u32,
+    #[arg(long, default_value_t = 8)]
+    max_listeners: u32,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    peercastoxide_tracing::init(env!("CARGO_CRATE_NAME"));
+
+    let args = Args::parse();
+
+    let listener = bind_dual_stack(args.port.get())?;
+    let (sender, receiver) = mpsc::channel(UPSTREAM_QUEUE_SIZE);
+    let servent = Arc::new(Servent::new(
+        Id(rand::random()),
+        args.port.get(),
+        Arc::new(Channel::new(args.channel_id)),
+        args.max_relays,
+        args.max_listeners,
+        sender,
+    ));
+    let futures = [
+        spawn(servent::listen(listener, servent.clone())),
+        spawn(upstream::relay(args.upstream, receiver, servent)),
+    ];
+    select_all(futures).await.0??;
+
+    Ok(())
+}
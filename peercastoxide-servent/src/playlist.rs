@@ -0,0 +1,43 @@
+use anyhow::Result;
+use peercastoxide_lib::{
+    pcp::http::{ok_response, ChannelRequest, RequestHead},
+    playlist::{create_playlist, is_authority, stream_extension, stream_url, PlaylistFormat},
+};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::servent::{Servent, AGENT_NAME};
+
+/// Answers `GET /pls/<id>` with a playlist pointing to `/stream/<id>` of this servent.
+pub async fn process_playlist(
+    mut writer: impl AsyncWrite + Unpin + Send + Sync,
+    head: &RequestHead,
+    req: &ChannelRequest,
+    servent: &Servent,
+) -> Result<()> {
+    let channel = &servent.channel;
+    let authority = match head.header("Host").filter(|x| is_authority(x)) {
+        Some(host) => host.to_owned(),
+        None => format!("127.0.0.1:{}", servent.port),
+    };
+    let info = channel.info();
+    let info = info.as_ref().map(|info| &info.0);
+    let format = req
+        .ext
+        .as_deref()
+        .and_then(PlaylistFormat::from_extension)
+        .unwrap_or_else(|| PlaylistFormat::for_stream_type(info.and_then(|x| x.r#type.as_deref())));
+    let url = stream_url(
+        &authority,
+        channel.id(),
+        &info.map(stream_extension).unwrap_or_default(),
+        req.tip.as_deref(),
+    );
+    let title = info
+        .map(|x| x.name.clone())
+        .unwrap_or_else(|| channel.id().to_string());
+    let body = create_playlist(format, &title, &url);
+    let response = ok_response(AGENT_NAME, format.mime_type());
+    writer.write_all(response.as_bytes()).await?;
+    writer.write_all(body.as_bytes()).await?;
+    Ok(())
+}
@@ -0,0 +1,606 @@
+use std::{
+    io::Cursor,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use peercastoxide_lib::net::canonical;
+use peercastoxide_lib::pcp::atom::{
+    from_unknown, to_unknown,
+    values::{AtomIpAddr, BcstGroup, Endpoint, Flg1, Id, VExP},
+    well_known_atoms::{Bcst, Host, Info, PcpOk, Pkt, Quit, StreamChan, Trck},
+    well_known_identifiers::{BCST, PCP, QUIT},
+    well_known_protocols::{handshake, handshake_incoming, write_unavailable},
+    AtomStreamReader, AtomStreamWriter, UnknownAtom,
+};
+use peercastoxide_lib::pcp::http::{
+    pcp_ok_response, pcp_unavailable_response, read_request_head, status_response, ChannelRequest,
+    ChannelRequestKind,
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    select, spawn,
+    sync::{broadcast::error::RecvError, mpsc},
+};
+use tracing::{debug, error, info, trace};
+
+use crate::{
+    channel::{Channel, ChannelEvent},
+    playlist, stream,
+};
+
+pub const AGENT_NAME: &str = concat!("PeerCastOxide/", env!("CARGO_PKG_VERSION"));
+pub const PCP_VERSION: u32 = 1218;
+const PCP_VERSION_VP: u32 = 27;
+const PCP_VERSION_EX_PREFIX: [u8; 2] = *b"OX";
+const PCP_VERSION_EX_NUMBER: u16 = 1;
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+const BCST_TTL: u8 = 11;
+/// Number of alternative hosts sent to a relay which couldn't be accepted.
+const UNAVAILABLE_HOSTS: usize = 8;
+
+#[derive(derive_new::new)]
+pub struct Servent {
+    pub session_id: Id,
+    pub port: u16,
+    pub channel: Arc<Channel>,
+    pub max_relays: u32,
+    pub max_listeners: u32,
+    /// Atoms to send to the upstream.
+    pub upstream_sender: mpsc::Sender<UnknownAtom>,
+}
+
+impl Servent {
+    /// `global` is our address as seen by the upstream; port 0 means we are firewalled.
+    pub fn create_host(&self, global: Option<(AtomIpAddr, u16)>) -> Host {
+        let channel = &self.channel;
+        let relays = channel.relays();
+        let listeners = channel.listeners();
+        let port = global.as_ref().map(|(_, port)| *port).unwrap_or(0);
+        let flg1 = [
+            (relays < self.max_relays, Flg1::RELAY),
+            (listeners < self.max_listeners, Flg1::DIRECT),
+            (port == 0, Flg1::PUSH),
+            (channel.is_receiving(), Flg1::RECV),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .fold(0, |flg1, (_, bit)| flg1 | bit);
+        Host {
+            cid: channel.id().clone(),
+            id: self.session_id.clone(),
+            ip_port: global
+                .map(|(ip, port)| Endpoint { ip, port })
+                .into_iter()
+                .collect(),
+            numl: listeners,
+            numr: relays,
+            uptm: channel.started_at().elapsed().as_secs() as u32,
+            ver: PCP_VERSION,
+            vevp: PCP_VERSION_VP,
+            vexp: VExP(PCP_VERSION_EX_PREFIX),
+            vexn: PCP_VERSION_EX_NUMBER,
+            flg1: Flg1(flg1),
+            oldp: None,
+            newp: None,
+            upip: None,
+            uppt: None,
+            uphp: None,
+        }
+    }
+
+    /// Reports this relay to the trackers.
+    pub fn create_bcst(&self, host: Host) -> Bcst {
+        Bcst {
+            grp: BcstGroup::TRACKERS,
+            hops: 0,
+            ttl: BCST_TTL,
+            from: self.session_id.clone(),
+            dest: None,
+            vers: PCP_VERSION,
+            vrvp: PCP_VERSION_VP,
+            vexp: VExP(PCP_VERSION_EX_PREFIX),
+            vexn: PCP_VERSION_EX_NUMBER,
+            cid: Some(self.channel.id().clone()),
+            chan: None,
+            host: Some(host),
+            push: None,
+        }
+    }
+}
+
+fn info_chan(channel: &Channel, info: &(Info, Trck)) -> StreamChan {
+    StreamChan {
+        id: channel.id().clone(),
+        bcid: channel.broadcast_id(),
+        info: Some(info.0.clone()),
+        trck: Some(info.1.clone()),
+        pkt: None,
+    }
+}
+
+fn pkt_chan(channel: &Channel, pkt: &Pkt) -> StreamChan {
+    StreamChan {
+        id: channel.id().clone(),
+        bcid: None,
+        info: None,
+        trck: None,
+        pkt: Some(pkt.clone()),
+    }
+}
+
+pub async fn write_status(writer: &mut (impl AsyncWrite + Unpin), status: &str) -> Result<()> {
+    writer
+        .write_all(status_response(AGENT_NAME, status).as_bytes())
+        .await?;
+    Ok(())
+}
+
+async fn process_ping(
+    reader: impl AsyncRead + Unpin + Send + Sync,
+    writer: impl AsyncWrite + Unpin + Send + Sync,
+    peer_ip: IpAddr,
+    servent: &Servent,
+) -> Result<()> {
+    let mut reader = AtomStreamReader::new(reader);
+    let mut writer = AtomStreamWriter::new(writer);
+    handshake(
+        &mut reader,
+        &mut writer,
+        &servent.session_id,
+        peer_ip,
+        AGENT_NAME,
+        PCP_VERSION,
+        PING_TIMEOUT,
+    )
+    .await?;
+    loop {
+        let atom = reader.read_unknown_atom().await?;
+        if atom.identifier().0.as_ref() == QUIT {
+            return Ok(());
+        }
+        trace!("{}", atom);
+    }
+}
+
+/// Forwards `bcst` atoms from a downstream relay to the upstream.
+async fn read_downstream(
+    mut reader: AtomStreamReader<impl AsyncRead + Unpin + Send + Sync>,
+    session_id: Id,
+    peer_addr: SocketAddr,
+    servent: Arc<Servent>,
+) -> Result<()> {
+    loop {
+        let atom = reader.read_unknown_atom().await?;
+        match atom.identifier().0.as_ref() {
+            BCST => {
+                let bcst: Bcst = from_unknown(atom)?;
+                if let Some(host) = bcst.host.as_ref().filter(|_| bcst.from == session_id) {
+                    if &host.cid == servent.channel.id() {
+                        let mut host = host.clone();
+                        host.add_verified_addr(peer_addr);
+                        servent.channel.set_relay_host(session_id.clone(), host);
+                    }
+                }
+                let Some(next) = bcst.next_hop() else {
+                    continue;
+                };
+                if next.from == servent.session_id || !next.grp.contains(BcstGroup::TRACKERS) {
+                    continue;
+                }
+                if let Err(err) = servent.upstream_sender.try_send(to_unknown(&next)?) {
+                    debug!("bcst dropped: {}", err);
+                }
+            }
+            QUIT => {
+                let quit: Quit = from_unknown(atom)?;
+                debug!("quit from downstream: {}", quit.0);
+                return Ok(());
+            }
+            _ => trace!("{}", atom),
+        }
+    }
+}
+
+async fn write_downstream(
+    writer: &mut AtomStreamWriter<impl AsyncWrite + Unpin + Send + Sync>,
+    channel: &Channel,
+) -> Result<()> {
+    let mut subscription = channel.subscribe();
+    if let Some(info) = &subscription.info {
+        writer.write_atom(&info_chan(channel, info)).await?;
+    }
+    for pkt in subscription.head.iter().chain(&subscription.packets) {
+        writer.write_atom(&pkt_chan(channel, pkt)).await?;
+    }
+    loop {
+        let event = match subscription.receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                debug!("skipped {} events", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        let chan = match event {
+            ChannelEvent::Info(info) => info_chan(channel, &info),
+            ChannelEvent::Head(pkt) | ChannelEvent::Data(pkt) => pkt_chan(channel, &pkt),
+        };
+        writer.write_atom(&chan).await?;
+    }
+}
+
+async fn process_relay(
+    reader: impl AsyncRead + Unpin + Send + Sync + 'static,
+    mut writer: impl AsyncWrite + Unpin + Send + Sync,
+    peer_ip: IpAddr,
+    servent: Arc<Servent>,
+) -> Result<()> {
+    let slot = servent.channel.try_add_relay(servent.max_relays);
+    let response = match slot {
+        Some(_) => pcp_ok_response(AGENT_NAME),
+        None => pcp_unavailable_response(AGENT_NAME),
+    };
+    writer.write_all(response.as_bytes()).await?;
+
+    let mut reader = AtomStreamReader::new(reader);
+    let mut writer = AtomStreamWriter::new(writer);
+    let handshake = handshake_incoming(
+        &mut reader,
+        &mut writer,
+        &servent.session_id,
+        peer_ip,
+        AGENT_NAME,
+        PCP_VERSION,
+        PING_TIMEOUT,
+    )
+    .await?;
+    let helo = handshake.helo;
+    if slot.is_none() {
+        let mut hosts = servent.channel.relay_hosts();
+        hosts.truncate(UNAVAILABLE_HOSTS);
+        debug!("relay refused: {} ({} alternatives)", peer_ip, hosts.len());
+        return write_unavailable(&mut writer, &hosts).await;
+    }
+    info!(
+        "relay connected: {} ({}, version {})",
+        peer_ip, helo.sid, handshake.version
+    );
+    writer.write_atom(&PcpOk(0)).await?;
+
+    let peer_addr = SocketAddr::new(handshake.peer_ip, handshake.peer_port);
+    let mut read = spawn(read_downstream(
+        reader,
+        helo.sid.clone(),
+        peer_addr,
+        servent.clone(),
+    ));
+    let result = select! {
+        result = write_downstream(&mut writer, &servent.channel) => result,
+        result = &mut read => result?,
+    };
+    read.abort();
+    servent.channel.remove_relay_host(&helo.sid);
+    info!("relay disconnected: {} ({})", peer_ip, helo.sid);
+    result
+}
+
+async fn process_http(
+    mut reader: impl AsyncBufRead + Unpin + Send + Sync + 'static,
+    mut writer: impl AsyncWrite + Unpin + Send + Sync,
+    peer_ip: IpAddr,
+    servent: Arc<Servent>,
+) -> Result<()> {
+    let head = read_request_head(&mut reader).await?;
+    trace!("{:?}", head);
+    if head.method != "GET" {
+        return write_status(&mut writer, "405 Method Not Allowed").await;
+    }
+    let req = match ChannelRequest::from_head(&head) {
+        Ok(Some(req)) if &req.channel_id == servent.channel.id() => req,
+        Ok(_) => return write_status(&mut writer, "404 Not Found").await,
+        Err(err) => {
+            debug!("{}", err);
+            return write_status(&mut writer, "400 Bad Request").await;
+        }
+    };
+    match req.kind {
+        ChannelRequestKind::Channel if req.is_relay() => {
+            process_relay(reader, writer, peer_ip, servent).await
+        }
+        ChannelRequestKind::Channel | ChannelRequestKind::Stream => {
+            stream::process_viewer(writer, servent).await
+        }
+        ChannelRequestKind::Pls => playlist::process_playlist(writer, &head, &req, &servent).await,
+    }
+}
+
+async fn process(stream: TcpStream, servent: Arc<Servent>) -> Result<()> {
+    let peer_ip = canonical(stream.peer_addr()?).ip();
+    let (mut reader, writer) = stream.into_split();
+    let mut prefix = [0u8; 4];
+    reader.read_exact(&mut prefix).await?;
+    let reader = BufReader::new(Cursor::new(prefix).chain(reader));
+    if &prefix == PCP {
+        return process_ping(reader, writer, peer_ip, &servent).await;
+    }
+    process_http(reader, writer, peer_ip, servent).await
+}
+
+pub async fn listen(listener: TcpListener, servent: Arc<Servent>) -> Result<()> {
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        trace!("accept: {}", addr);
+        let servent = servent.clone();
+        spawn(async move {
+            if let Err(e) = process(socket, servent).await {
+                error!("{}: {:?}", addr, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, sync::Arc, time::Duration};
+
+    use peercastoxide_lib::pcp::atom::{
+        values::{Id, PktType},
+        well_known_atoms::{Bcst, Helo, Info, PcpOk, Pkt, StreamChan, Trck},
+        well_known_protocols::{handshake_incoming, handshake_outgoing, read_unavailable},
+        AtomStreamReader, AtomStreamWriter,
+    };
+    use peercastoxide_lib::pcp::http::{read_request_head, read_response_head};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+        spawn,
+        sync::mpsc,
+        time::{sleep, timeout},
+    };
+
+    use crate::{channel::Channel, upstream};
+
+    use super::{listen, Servent};
+
+    fn pkt(r#type: PktType, pos: u32, data: &[u8]) -> Pkt {
+        Pkt {
+            r#type,
+            pos,
+            data: data.to_vec(),
+            cont: None,
+        }
+    }
+
+    fn packets() -> [Pkt; 4] {
+        [
+            pkt(PktType::HEAD, 0, b"head"),
+            pkt(PktType::DATA, 4, b"data0"),
+            pkt(PktType::DATA, 9, b"data1"),
+            Pkt {
+                cont: Some(1),
+                ..pkt(PktType::DATA, 14, b"data2")
+            },
+        ]
+    }
+
+    fn stream_chan(id: &Id, pkt: Pkt) -> StreamChan {
+        StreamChan {
+            id: id.clone(),
+            bcid: None,
+            info: None,
+            trck: None,
+            pkt: Some(pkt),
+        }
+    }
+
+    /// Serves one `GET /channel/<id>` request and returns the first `bcst` reported back.
+    async fn tracker(listener: TcpListener, channel_id: Id) -> Bcst {
+        let (stream, peer_addr) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let req = read_request_head(&mut reader).await.unwrap();
+        assert_eq!(req.path, format!("/channel/{}", channel_id));
+        assert_eq!(req.header("x-peercast-pcp"), Some("1"));
+        writer
+            .write_all(b"HTTP/1.0 200 OK\r\nContent-Type: application/x-peercast-pcp\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut reader = AtomStreamReader::new(reader);
+        let mut writer = AtomStreamWriter::new(writer);
+        handshake_incoming(
+            &mut reader,
+            &mut writer,
+            &Id([9; 16]),
+            peer_addr.ip(),
+            "tracker",
+            1218,
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        writer.write_atom(&PcpOk(0)).await.unwrap();
+        let info = StreamChan {
+            id: channel_id.clone(),
+            bcid: Some(Id([2; 16])),
+            info: Some(Info {
+                name: "name".into(),
+                bitr: Some(500),
+                gnre: "".into(),
+                url: "".into(),
+                desc: "".into(),
+                cmnt: "".into(),
+                r#type: Some("FLV".into()),
+                styp: Some("flv".into()),
+                sext: Some(".flv".into()),
+            }),
+            trck: Some(Trck {
+                titl: "".into(),
+                crea: "".into(),
+                url: "".into(),
+                albm: "".into(),
+                gnre: None,
+            }),
+            pkt: None,
+        };
+        writer.write_atom(&info).await.unwrap();
+        for pkt in packets() {
+            writer
+                .write_atom(&stream_chan(&channel_id, pkt))
+                .await
+                .unwrap();
+        }
+        let bcst: Bcst = reader.read_atom().await.unwrap();
+        // keep the connection open until the test finishes
+        spawn(async move {
+            while reader.read_unknown_atom().await.is_ok() {}
+            drop(writer);
+        });
+        bcst
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_relay_on_localhost() {
+        let channel_id = Id([1; 16]);
+        let tracker_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tracker_addr = tracker_listener.local_addr().unwrap();
+        let tracker = spawn(tracker(tracker_listener, channel_id.clone()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::channel(8);
+        let channel = Arc::new(Channel::new(channel_id.clone()));
+        let servent = Arc::new(Servent::new(
+            Id([3; 16]),
+            port,
+            channel.clone(),
+            1,
+            1,
+            sender,
+        ));
+        spawn(listen(listener, servent.clone()));
+        spawn(upstream::relay(tracker_addr, receiver, servent));
+
+        let bcst = timeout(Duration::from_secs(10), tracker)
+            .await
+            .unwrap()
+            .unwrap();
+        let host = bcst.host.unwrap();
+        assert_eq!(bcst.from, Id([3; 16]));
+        assert_eq!(host.cid, channel_id);
+        // the tracker could ping us back
+        assert_eq!(host.ip_port[0].port, port);
+        while channel.subscribe().packets.len() < 3 {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // HTTP viewer
+        let mut viewer = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let req = format!("GET /stream/{}.flv HTTP/1.0\r\n\r\n", channel_id);
+        viewer.write_all(req.as_bytes()).await.unwrap();
+        let mut viewer = BufReader::new(viewer);
+        let (status, headers) = read_response_head(&mut viewer).await.unwrap();
+        assert_eq!(status, 200);
+        assert!(headers.contains(&("Content-Type".into(), "video/x-flv".into())));
+        // starts from the newest keyframe
+        let mut body = [0u8; 14];
+        viewer.read_exact(&mut body).await.unwrap();
+        assert_eq!(&body, b"headdata1data2");
+
+        // playlist
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let req = format!(
+            "GET /pls/{}.m3u?tip=127.0.0.1:7144 HTTP/1.0\r\nHost: localhost:{}\r\n\r\n",
+            channel_id, port
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let (status, headers) = read_response_head(&mut stream).await.unwrap();
+        assert_eq!(status, 200);
+        assert!(headers.contains(&("Content-Type".into(), "audio/x-mpegurl".into())));
+        let mut body = String::new();
+        stream.read_to_string(&mut body).await.unwrap();
+        assert_eq!(
+            body,
+            format!(
+                "#EXTM3U\r\n#EXTINF:-1,name\r\nhttp://localhost:{}/stream/{}.flv?tip=127.0.0.1:7144\r\n",
+                port, channel_id
+            )
+        );
+
+        // downstream relay
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let req = format!(
+            "GET /channel/{} HTTP/1.0\r\nx-peercast-pcp: 1\r\n\r\n",
+            channel_id
+        );
+        writer.write_all(req.as_bytes()).await.unwrap();
+        let mut reader = BufReader::new(reader);
+        let (status, _) = read_response_head(&mut reader).await.unwrap();
+        assert_eq!(status, 200);
+        let mut reader = AtomStreamReader::new(reader);
+        let mut writer = AtomStreamWriter::new(writer);
+        let helo = Helo {
+            sid: Id([4; 16]),
+            agnt: None,
+            ver: None,
+            port: None,
+            ping: None,
+            bcid: None,
+        };
+        let oleh = handshake_outgoing(&mut reader, &mut writer, &helo)
+            .await
+            .unwrap();
+        assert_eq!(oleh.sid, Id([3; 16]));
+        let _: PcpOk = reader.read_atom().await.unwrap();
+        let info: StreamChan = reader.read_atom().await.unwrap();
+        assert_eq!(info.bcid, Some(Id([2; 16])));
+        assert_eq!(info.info.unwrap().name, "name");
+        let mut received = Vec::new();
+        for _ in 0..4 {
+            let chan: StreamChan = reader.read_atom().await.unwrap();
+            received.push(chan.pkt.unwrap());
+        }
+        assert_eq!(received, packets());
+        let downstream = Servent::new(
+            Id([4; 16]),
+            7200,
+            Arc::new(Channel::new(channel_id.clone())),
+            1,
+            1,
+            mpsc::channel(1).0,
+        );
+        let host = downstream.create_host(Some((IpAddr::from([127, 0, 0, 1]).into(), 7200)));
+        writer
+            .write_atom(&downstream.create_bcst(host.clone()))
+            .await
+            .unwrap();
+        while channel.relay_hosts().is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // relay slots are full, so the downstream relay is offered instead
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        writer.write_all(req.as_bytes()).await.unwrap();
+        let mut reader = BufReader::new(reader);
+        let (status, _) = read_response_head(&mut reader).await.unwrap();
+        assert_eq!(status, 503);
+        let mut reader = AtomStreamReader::new(reader);
+        let mut writer = AtomStreamWriter::new(writer);
+        let helo = Helo {
+            sid: Id([5; 16]),
+            ..helo
+        };
+        handshake_outgoing(&mut reader, &mut writer, &helo)
+            .await
+            .unwrap();
+        let hosts = read_unavailable(&mut reader).await.unwrap();
+        assert_eq!(hosts, [host]);
+    }
+}
@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use peercastoxide_lib::pcp::{
+    atom::well_known_atoms::{Info, Pkt},
+    http::ok_response,
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::broadcast::error::RecvError,
+};
+use tracing::debug;
+
+use crate::{
+    channel::ChannelEvent,
+    servent::{write_status, Servent, AGENT_NAME},
+};
+
+/// `styp` is a MIME type in PeerCastStation; PeerCast only sets `type`.
+pub fn content_type(info: Option<&Info>) -> String {
+    if let Some(styp) = info.and_then(|info| info.styp.as_deref()) {
+        if styp.contains('/') {
+            return styp.to_owned();
+        }
+    }
+    let r#type = info.and_then(|info| info.r#type.as_deref()).unwrap_or("");
+    match r#type.to_ascii_uppercase().as_str() {
+        "FLV" => "video/x-flv",
+        "MKV" => "video/x-matroska",
+        "WEBM" => "video/webm",
+        "WMV" | "WMA" => "video/x-ms-asf",
+        "MP3" => "audio/mpeg",
+        "AAC" => "audio/aac",
+        "OGG" | "OGM" => "application/ogg",
+        "NSV" => "video/nsv",
+        _ => "application/octet-stream",
+    }
+    .to_owned()
+}
+
+/// Packets without the `cont` flag start at a keyframe.
+fn is_keyframe(pkt: &Pkt) -> bool {
+    pkt.cont.unwrap_or(0) == 0
+}
+
+/// Index of the newest keyframe in the buffer, so that a new viewer starts close to live.
+fn start_index(packets: &[Arc<Pkt>]) -> usize {
+    packets
+        .iter()
+        .rposition(|pkt| is_keyframe(pkt))
+        .unwrap_or(0)
+}
+
+/// Drops data until the head has been sent and the stream reaches a keyframe.
+#[derive(Default)]
+struct KeyframeFilter {
+    head_sent: bool,
+    synced: bool,
+}
+
+impl KeyframeFilter {
+    fn head(&mut self) {
+        self.head_sent = true;
+        self.synced = false;
+    }
+
+    fn data(&mut self, pkt: &Pkt) -> bool {
+        if !self.head_sent {
+            return false;
+        }
+        if !self.synced && !is_keyframe(pkt) {
+            return false;
+        }
+        self.synced = true;
+        true
+    }
+
+    /// Packets were skipped, so the next data may start in the middle of a frame.
+    fn lagged(&mut self) {
+        self.synced = false;
+    }
+}
+
+pub async fn process_viewer(
+    mut writer: impl AsyncWrite + Unpin + Send + Sync,
+    servent: Arc<Servent>,
+) -> Result<()> {
+    let channel = &servent.channel;
+    let Some(_slot) = channel.try_add_listener(servent.max_listeners) else {
+        return write_status(&mut writer, "503 Service Unavailable").await;
+    };
+    let mut subscription = channel.subscribe();
+    let response = ok_response(
+        AGENT_NAME,
+        &content_type(subscription.info.as_ref().map(|info| &info.0)),
+    );
+    writer.write_all(response.as_bytes()).await?;
+
+    let mut filter = KeyframeFilter::default();
+    if let Some(head) = &subscription.head {
+        writer.write_all(&head.data).await?;
+        filter.head();
+    }
+    let packets = &subscription.packets;
+    for pkt in &packets[start_index(packets)..] {
+        if filter.data(pkt) {
+            writer.write_all(&pkt.data).await?;
+        }
+    }
+    loop {
+        match subscription.receiver.recv().await {
+            Ok(ChannelEvent::Head(pkt)) => {
+                writer.write_all(&pkt.data).await?;
+                filter.head();
+            }
+            Ok(ChannelEvent::Data(pkt)) => {
+                if filter.data(&pkt) {
+                    writer.write_all(&pkt.data).await?;
+                }
+            }
+            Ok(ChannelEvent::Info(_)) => {}
+            Err(RecvError::Lagged(skipped)) => {
+                debug!("viewer too slow, skipped {} events", skipped);
+                filter.lagged();
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use peercastoxide_lib::pcp::atom::{
+        values::PktType,
+        well_known_atoms::{Info, Pkt},
+    };
+
+    use super::{content_type, start_index, KeyframeFilter};
+
+    fn pkt(cont: Option<u8>) -> Pkt {
+        Pkt {
+            r#type: PktType::DATA,
+            pos: 0,
+            data: vec![],
+            cont,
+        }
+    }
+
+    #[test]
+    fn test_content_type() {
+        let mut info = Info {
+            name: "".into(),
+            bitr: None,
+            gnre: "".into(),
+            url: "".into(),
+            desc: "".into(),
+            cmnt: "".into(),
+            r#type: Some("MKV".into()),
+            styp: None,
+            sext: None,
+        };
+        assert_eq!(content_type(Some(&info)), "video/x-matroska");
+        info.styp = Some("video/webm".into());
+        assert_eq!(content_type(Some(&info)), "video/webm");
+        assert_eq!(content_type(None), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_start_from_newest_keyframe() {
+        let packets = [pkt(None), pkt(Some(0)), pkt(Some(1)), pkt(Some(1))].map(Arc::new);
+        assert_eq!(start_index(&packets), 1);
+        assert_eq!(start_index(&[]), 0);
+    }
+
+    #[test]
+    fn test_filter_waits_for_head_and_keyframe() {
+        let mut filter = KeyframeFilter::default();
+        assert!(!filter.data(&pkt(None)));
+        filter.head();
+        assert!(!filter.data(&pkt(Some(1))));
+        assert!(filter.data(&pkt(None)));
+        assert!(filter.data(&pkt(Some(1))));
+        filter.lagged();
+        assert!(!filter.data(&pkt(Some(1))));
+        assert!(filter.data(&pkt(Some(0))));
+    }
+}
@@ -0,0 +1,159 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::{bail, Result};
+use peercastoxide_lib::pcp::{
+    atom::{
+        from_unknown,
+        values::{AtomIpAddr, PktType},
+        well_known_atoms::{Helo, Host, Quit, StreamChan},
+        well_known_identifiers::{BCST, CHAN, OK, QUIT},
+        well_known_protocols::{handshake_outgoing, read_unavailable},
+        AtomStreamReader, AtomStreamWriter, UnknownAtom,
+    },
+    http::{read_response_head, ChannelRequest, ChannelRequestKind},
+    relay_candidates::RelayCandidates,
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt, BufReader},
+    net::{lookup_host, TcpStream, ToSocketAddrs},
+    select,
+    sync::mpsc,
+    time::interval,
+};
+use tracing::{debug, info, trace};
+
+use crate::servent::{Servent, AGENT_NAME, PCP_VERSION};
+
+const HOST_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_UPSTREAM_ATTEMPTS: usize = 8;
+
+async fn write_upstream(
+    mut writer: AtomStreamWriter<impl AsyncWrite + Unpin + Send + Sync>,
+    receiver: &mut mpsc::Receiver<UnknownAtom>,
+    global: Option<(AtomIpAddr, u16)>,
+    servent: &Servent,
+) -> Result<()> {
+    let mut report = interval(HOST_REPORT_INTERVAL);
+    loop {
+        select! {
+            _ = report.tick() => {
+                let host = servent.create_host(global.clone());
+                writer.write_atom(&servent.create_bcst(host)).await?;
+            }
+            atom = receiver.recv() => {
+                let Some(atom) = atom else {
+                    return Ok(());
+                };
+                writer.write_unknown_atom(&atom).await?;
+            }
+        }
+    }
+}
+
+fn handle_stream_chan(chan: StreamChan, servent: &Servent) -> Result<()> {
+    let channel = &servent.channel;
+    if &chan.id != channel.id() {
+        bail!("unexpected channel: {}", chan.id);
+    }
+    if let Some(bcid) = chan.bcid {
+        channel.set_broadcast_id(bcid);
+    }
+    if let (Some(info), Some(trck)) = (chan.info, chan.trck) {
+        channel.set_info(info, trck);
+    }
+    if let Some(pkt) = chan.pkt {
+        match pkt.r#type {
+            PktType::HEAD => channel.set_head(pkt),
+            PktType::DATA => channel.push_data(pkt),
+            _ => trace!("{:?}", pkt.r#type),
+        }
+    }
+    Ok(())
+}
+
+enum Joined {
+    /// The upstream closed the channel with `quit`.
+    Finished,
+    /// The upstream was full and offered these hosts instead.
+    Unavailable(Vec<Host>),
+}
+
+/// Joins the channel via `GET /channel/<id>` and feeds the received packets to the channel.
+async fn join(
+    addr: SocketAddr,
+    receiver: &mut mpsc::Receiver<UnknownAtom>,
+    servent: &Servent,
+) -> Result<Joined> {
+    let stream = TcpStream::connect(addr).await?;
+    let (reader, mut writer) = stream.into_split();
+    let mut request =
+        ChannelRequest::new(ChannelRequestKind::Channel, servent.channel.id().clone());
+    request.pcp = Some(1);
+    request.port = Some(servent.port);
+    writer
+        .write_all(request.to_request(AGENT_NAME).as_bytes())
+        .await?;
+    let mut reader = BufReader::new(reader);
+    let (status, _headers) = read_response_head(&mut reader).await?;
+    if status != 200 && status != 503 {
+        bail!("upstream responded {}", status);
+    }
+
+    let mut reader = AtomStreamReader::new(reader);
+    let mut writer = AtomStreamWriter::new(writer);
+    let helo = Helo {
+        sid: servent.session_id.clone(),
+        agnt: Some(AGENT_NAME.into()),
+        ver: Some(PCP_VERSION),
+        port: None,
+        ping: Some(servent.port),
+        bcid: None,
+    };
+    let oleh = handshake_outgoing(&mut reader, &mut writer, &helo).await?;
+    if status == 503 {
+        return Ok(Joined::Unavailable(read_unavailable(&mut reader).await?));
+    }
+    info!("joined: {} ({})", addr, oleh.sid);
+    let global = oleh.rip.map(|ip| (ip, oleh.port.unwrap_or(0)));
+
+    let read = async {
+        loop {
+            let atom = reader.read_unknown_atom().await?;
+            match atom.identifier().0.as_ref() {
+                OK => trace!("{}", atom),
+                CHAN => handle_stream_chan(from_unknown(atom)?, servent)?,
+                BCST => trace!("{}", atom),
+                QUIT => {
+                    let quit: Quit = from_unknown(atom)?;
+                    info!("quit from upstream: {}", quit.0);
+                    return Ok(Joined::Finished);
+                }
+                _ => debug!("unknown atom: {}", atom),
+            }
+        }
+    };
+    select! {
+        result = read => result,
+        result = write_upstream(writer, receiver, global, servent) => result.map(|_| Joined::Finished),
+    }
+}
+
+/// Relays the channel from `addr`, or from the alternatives offered when it is full.
+pub async fn relay(
+    addr: impl ToSocketAddrs,
+    mut receiver: mpsc::Receiver<UnknownAtom>,
+    servent: Arc<Servent>,
+) -> Result<()> {
+    let mut candidates = RelayCandidates::new(lookup_host(addr).await?, MAX_UPSTREAM_ATTEMPTS);
+    while let Some(addr) = candidates.next() {
+        match join(addr, &mut receiver, &servent).await {
+            Ok(Joined::Finished) => return Ok(()),
+            Ok(Joined::Unavailable(hosts)) => {
+                info!("{} is full, {} alternatives", addr, hosts.len());
+                candidates.add_hosts(&hosts);
+            }
+            Err(err) => info!("{}: {}", addr, err),
+        }
+    }
+    bail!("no upstream available")
+}
@@ -0,0 +1,276 @@
+use std::{collections::HashMap, sync::Arc};
+
+use peercastoxide_lib::pcp::atom::{
+    values::{BcstGroup, Id},
+    well_known_atoms::Bcst,
+    well_known_identifiers::{HOPS, TTL},
+    UnknownAtom,
+};
+use tokio::sync::mpsc;
+use tracing::{debug, trace};
+
+const SEND_QUEUE_SIZE: usize = 64;
+
+pub type SessionKey = u64;
+
+struct Session {
+    peer_session_id: Id,
+    groups: BcstGroup,
+    sender: mpsc::Sender<Arc<UnknownAtom>>,
+}
+
+/// `atom` with `ttl` and `hops` of `next`, keeping the children `Bcst` doesn't know.
+fn with_hop_of(atom: &UnknownAtom, next: &Bcst) -> UnknownAtom {
+    let mut atom = atom.clone();
+    if let UnknownAtom::Parent(parent) = &mut atom {
+        for child in parent.children_mut() {
+            match child.identifier().0.as_ref() {
+                TTL => *child = UnknownAtom::u8(TTL, next.ttl),
+                HOPS => *child = UnknownAtom::u8(HOPS, next.hops),
+                _ => {}
+            }
+        }
+    }
+    atom
+}
+
+/// Fans out received `bcst` atoms to the other PCP sessions.
+#[derive(Default)]
+pub struct BcstRouter {
+    next_key: SessionKey,
+    sessions: HashMap<SessionKey, Session>,
+}
+
+impl BcstRouter {
+    pub fn register(
+        &mut self,
+        peer_session_id: Id,
+    ) -> (SessionKey, mpsc::Receiver<Arc<UnknownAtom>>) {
+        let (sender, receiver) = mpsc::channel(SEND_QUEUE_SIZE);
+        let key = self.next_key;
+        self.next_key += 1;
+        let session = Session {
+            peer_session_id,
+            groups: BcstGroup::empty(),
+            sender,
+        };
+        self.sessions.insert(key, session);
+        (key, receiver)
+    }
+
+    pub fn unregister(&mut self, key: SessionKey) {
+        self.sessions.remove(&key);
+    }
+
+    pub fn join_groups(&mut self, key: SessionKey, groups: BcstGroup) {
+        if let Some(session) = self.sessions.get_mut(&key) {
+            session.groups |= groups;
+        }
+    }
+
+    /// Queues `atom`, received as `bcst`, for the next hop. Returns the number of sessions it was
+    /// queued for.
+    pub fn route(
+        &self,
+        src: SessionKey,
+        own_session_id: &Id,
+        bcst: &Bcst,
+        atom: &UnknownAtom,
+    ) -> usize {
+        if &bcst.from == own_session_id {
+            debug!("bcst looped back");
+            return 0;
+        }
+        let Some(next) = bcst.next_hop() else {
+            trace!("bcst ttl expired");
+            return 0;
+        };
+        let next = Arc::new(with_hop_of(atom, &next));
+        let mut delivered = 0;
+        for (key, session) in &self.sessions {
+            if *key == src || !bcst.is_deliverable_to(&session.peer_session_id, session.groups) {
+                continue;
+            }
+            if let Err(err) = session.sender.try_send(next.clone()) {
+                debug!("bcst dropped for {}: {}", session.peer_session_id, err);
+                continue;
+            }
+            delivered += 1;
+        }
+        delivered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use peercastoxide_lib::pcp::atom::{
+        from_unknown, to_unknown,
+        values::{AtomIpAddr, BcstGroup, Endpoint, Flg1, Id, VExP},
+        well_known_atoms::{Bcst, Chan, Host, Info, Push, Trck},
+        UnknownAtom,
+    };
+
+    use super::BcstRouter;
+
+    fn route(router: &BcstRouter, src: super::SessionKey, own: &Id, bcst: &Bcst) -> usize {
+        router.route(src, own, bcst, &to_unknown(bcst).unwrap())
+    }
+
+    fn bcst(grp: BcstGroup, ttl: u8, from: &Id) -> Bcst {
+        Bcst {
+            grp,
+            hops: 0,
+            ttl,
+            from: from.clone(),
+            dest: None,
+            vers: 1218,
+            vrvp: 27,
+            vexp: VExP([b'I', b'M']),
+            vexn: 51,
+            cid: None,
+            chan: Some(Chan {
+                id: Id([4; 16]),
+                bcid: Id([5; 16]),
+                info: Info {
+                    name: "name".into(),
+                    bitr: None,
+                    gnre: "".into(),
+                    url: "".into(),
+                    desc: "".into(),
+                    cmnt: "".into(),
+                    r#type: None,
+                    styp: None,
+                    sext: None,
+                },
+                trck: Trck {
+                    titl: "".into(),
+                    crea: "".into(),
+                    url: "".into(),
+                    albm: "".into(),
+                    gnre: None,
+                },
+            }),
+            host: Some(Host {
+                cid: Id([4; 16]),
+                id: from.clone(),
+                ip_port: vec![Endpoint {
+                    ip: AtomIpAddr::from([127, 0, 0, 1]),
+                    port: 7144,
+                }],
+                numl: 0,
+                numr: 0,
+                uptm: 0,
+                ver: 1218,
+                vevp: 27,
+                vexp: VExP([b'I', b'M']),
+                vexn: 51,
+                flg1: Flg1(0),
+                oldp: None,
+                newp: None,
+                upip: None,
+                uppt: None,
+                uphp: None,
+            }),
+            push: None,
+        }
+    }
+
+    #[test]
+    fn test_route_to_matching_group() {
+        let own = Id([0; 16]);
+        let mut router = BcstRouter::default();
+        let (src, mut src_rx) = router.register(Id([1; 16]));
+        let (tracker, mut tracker_rx) = router.register(Id([2; 16]));
+        let (relay, mut relay_rx) = router.register(Id([3; 16]));
+        router.join_groups(src, BcstGroup::TRACKERS);
+        router.join_groups(tracker, BcstGroup::TRACKERS);
+        router.join_groups(relay, BcstGroup::RELAYS);
+
+        let before = bcst(BcstGroup::TRACKERS, 7, &Id([1; 16]));
+        route(&router, src, &own, &before);
+
+        let forwarded = tracker_rx.try_recv().unwrap();
+        let forwarded: Bcst = from_unknown((*forwarded).clone()).unwrap();
+        assert_eq!(forwarded, before.next_hop().unwrap());
+        assert!(src_rx.try_recv().is_err());
+        assert!(relay_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_route_drops_expired_and_looped() {
+        let own = Id([0; 16]);
+        let mut router = BcstRouter::default();
+        let (src, _src_rx) = router.register(Id([1; 16]));
+        let (relay, mut relay_rx) = router.register(Id([2; 16]));
+        router.join_groups(relay, BcstGroup::RELAYS);
+
+        route(
+            &router,
+            src,
+            &own,
+            &bcst(BcstGroup::RELAYS, 1, &Id([1; 16])),
+        );
+        route(&router, src, &own, &bcst(BcstGroup::RELAYS, 7, &own));
+        route(
+            &router,
+            src,
+            &own,
+            &bcst(BcstGroup::RELAYS, 7, &Id([2; 16])),
+        );
+
+        assert!(relay_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_route_push_to_dest() {
+        let own = Id([0; 16]);
+        let mut router = BcstRouter::default();
+        let (src, _src_rx) = router.register(Id([1; 16]));
+        let (tracker, mut tracker_rx) = router.register(Id([2; 16]));
+        let (_relay, mut relay_rx) = router.register(Id([3; 16]));
+        router.join_groups(tracker, BcstGroup::TRACKERS);
+
+        let push = Bcst {
+            dest: Some(Id([2; 16])),
+            chan: None,
+            host: None,
+            push: Some(Push {
+                ip: AtomIpAddr::from([127, 0, 0, 1]),
+                port: 7144,
+                cid: Id([4; 16]),
+            }),
+            ..bcst(BcstGroup::ALL, 7, &Id([1; 16]))
+        };
+        assert_eq!(route(&router, src, &own, &push), 1);
+
+        let forwarded: Bcst = from_unknown((*tracker_rx.try_recv().unwrap()).clone()).unwrap();
+        assert_eq!(forwarded.push, push.push);
+        assert!(relay_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_route_keeps_unknown_children() {
+        let own = Id([0; 16]);
+        let mut router = BcstRouter::default();
+        let (src, _src_rx) = router.register(Id([1; 16]));
+        let (relay, mut relay_rx) = router.register(Id([2; 16]));
+        router.join_groups(relay, BcstGroup::RELAYS);
+
+        let before = bcst(BcstGroup::RELAYS, 7, &Id([1; 16]));
+        let mut atom = to_unknown(&before).unwrap();
+        let UnknownAtom::Parent(parent) = &mut atom else {
+            unreachable!();
+        };
+        let unknown = UnknownAtom::child(*b"xxxx", vec![1, 2, 3]);
+        parent.children_mut().push(unknown.clone());
+        assert_eq!(router.route(src, &own, &before, &atom), 1);
+
+        let forwarded = relay_rx.try_recv().unwrap();
+        let UnknownAtom::Parent(parent) = &*forwarded else {
+            unreachable!();
+        };
+        assert_eq!(parent.children().last(), Some(&unknown));
+        let forwarded: Bcst = from_unknown((*forwarded).clone()).unwrap();
+        assert_eq!(forwarded, before.next_hop().unwrap());
+    }
+}
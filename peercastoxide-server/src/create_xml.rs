@@ -1,7 +1,8 @@
-use std::{net::SocketAddr, time::Instant};
+use std::time::Instant;
 
 use peercastoxide_lib::{
-    pcp::atom::well_known_atoms::Bcst,
+    host_cache::HostCache,
+    pcp::atom::well_known_atoms::{Chan, Host as HostAtom},
     peercast_xml::{
         self, Bandwidth, Channel, ChannelsFound, ChannelsRelayed, Connections, Hits, Host, Servent,
         Track,
@@ -9,36 +10,46 @@ use peercastoxide_lib::{
 };
 
 pub struct Record {
-    pub bcst: Bcst,
+    pub chan: Chan,
+    pub host: HostAtom,
+    pub hops: u8,
     pub created_at: Instant,
     pub updated_at: Instant,
 }
 
 fn to_channel(record: &Record) -> Channel {
-    let bcst = &record.bcst;
-    let host = &bcst.host;
+    let host = &record.host;
     let flg1 = &host.flg1;
-    let chan = &bcst.chan;
+    let chan = &record.chan;
     let info = &chan.info;
     let trck = &chan.trck;
-    let hosts = vec![Host {
-        ip: host
-            .ip_port
-            .first()
-            .map(|(ip, port)| SocketAddr::new(ip.0, *port)),
-        hops: bcst.hops,
-        listeners: host.numl,
-        relays: host.numr,
-        uptime: host.uptm,
-        push: flg1.push(),
-        relay: flg1.relay(),
-        direct: flg1.direct(),
-        cin: flg1.cin(),
-        stable: 0,
-        version: host.ver,
-        update: record.updated_at.elapsed().as_secs(),
-        tracker: flg1.tracker(),
-    }];
+    // One entry per address family so that both IPv4 and IPv6 viewers find a tip.
+    let mut addrs = [host.global_v4(), host.global_v6()]
+        .into_iter()
+        .flatten()
+        .map(Some)
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        addrs.push(None);
+    }
+    let hosts = addrs
+        .into_iter()
+        .map(|ip| Host {
+            ip,
+            hops: record.hops,
+            listeners: host.numl,
+            relays: host.numr,
+            uptime: host.uptm,
+            push: flg1.push(),
+            relay: flg1.relay(),
+            direct: flg1.direct(),
+            cin: flg1.cin(),
+            stable: 0,
+            version: host.ver,
+            update: record.updated_at.elapsed().as_secs(),
+            tracker: flg1.tracker(),
+        })
+        .collect::<Vec<_>>();
     Channel {
         name: info.name.clone(),
         id: chan.id.to_string(),
@@ -54,17 +65,12 @@ fn to_channel(record: &Record) -> Channel {
         skip: None,
         bcflags: 0,
         hits: Hits {
-            hosts: 1,
-            listeners: hosts.iter().map(|x| x.listeners).sum(),
-            relays: hosts.iter().map(|x| x.relays).sum(),
-            firewalled: {
-                host.ip_port
-                    .first()
-                    .map(|&(_, port)| port == 0)
-                    .unwrap_or(true)
-            },
+            hosts: hosts.len() as u32,
+            listeners: host.numl,
+            relays: host.numr,
+            firewalled: host.global().map(|x| x.port() == 0).unwrap_or(true),
             closest: host.oldp.unwrap_or_default(),
-            furthest: bcst.hops,
+            furthest: record.hops,
             newest: host.newp.unwrap_or_default(),
             host: hosts,
         },
@@ -79,7 +85,12 @@ fn to_channel(record: &Record) -> Channel {
     }
 }
 
-pub fn create_xml(total_connections: u32, server_start_time: Instant, db: &[&Record]) -> String {
+pub fn create_xml(
+    total_connections: u32,
+    server_start_time: Instant,
+    db: &[&Record],
+    host_cache: &HostCache,
+) -> String {
     let uptime = server_start_time.elapsed().as_secs();
     let xml = peercast_xml::Peercast {
         session: None,
@@ -98,7 +109,7 @@ pub fn create_xml(total_connections: u32, server_start_time: Instant, db: &[&Rec
             total: db.len() as u32,
             channel: db.iter().map(|x| to_channel(x)).collect(),
         },
-        host_cache: None,
+        host_cache: Some(host_cache.to_xml()),
     };
     format!(
         "<?xml version=\"1.0\" encoding=\"utf-8\" ?>\n{}",
@@ -1,3 +1,4 @@
+mod bcst_router;
 mod create_xml;
 
 use std::num::NonZeroU16;
@@ -5,7 +6,6 @@ use std::num::NonZeroU16;
 use clap::Parser;
 
 mod pcp_server;
-mod tracing_helper;
 
 #[derive(Debug, clap::Parser)]
 #[command(version, about, long_about = None)]
@@ -18,7 +18,7 @@ struct Args {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_helper::init();
+    peercastoxide_tracing::init(env!("CARGO_CRATE_NAME"));
 
     let args = Args::parse();
 
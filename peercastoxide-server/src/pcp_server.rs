@@ -1,8 +1,7 @@
 use std::{
     collections::HashMap,
     future::Future,
-    net::{IpAddr, SocketAddr},
-    str::FromStr,
+    net::SocketAddr,
     sync::{Arc, RwLock},
     time::{Duration, Instant},
 };
@@ -12,48 +11,88 @@ use futures::future::select_all;
 use http_body_util::Full;
 use hyper::{body::Bytes, header::CONTENT_TYPE, server::conn::http1, Method, Response, StatusCode};
 use hyper_util::rt::TokioIo;
-use peercastoxide_lib::pcp::atom::{
-    from_unknown,
-    values::Id,
-    well_known_atoms::{Bcst, Quit},
-    well_known_identifiers::{BCST, QUIT},
-    well_known_protocols::handshake,
-    AtomStreamReader, AtomStreamWriter,
+use peercastoxide_lib::{
+    host_cache::HostCache,
+    net::{bind_dual_stack, canonical},
+    pcp::{
+        atom::{
+            from_unknown,
+            values::{BcstGroup, Id},
+            well_known_atoms::{Bcst, Quit},
+            well_known_identifiers::{BCST, QUIT},
+            well_known_protocols::handshake,
+            AtomStreamReader, AtomStreamWriter,
+        },
+        http::{ChannelRequest, ChannelRequestKind},
+    },
+    playlist::{create_playlist, stream_extension, stream_url, PlaylistFormat},
 };
 use tokio::{
-    net::{TcpListener, TcpStream, ToSocketAddrs},
+    net::{TcpListener, TcpStream},
     spawn,
     time::timeout,
 };
-use tracing::error;
+use tracing::{debug, error};
 
-use crate::create_xml::{create_xml, Record};
+use crate::{
+    bcst_router::{BcstRouter, SessionKey},
+    create_xml::{create_xml, Record},
+};
+
+pub struct Db {
+    pub session_id: Id,
+    pub connections: u32,
+    pub channels: HashMap<Id, Record>,
+    pub host_cache: HostCache,
+    pub router: BcstRouter,
+}
 
-pub type Db = (u32, HashMap<Id, Record>);
+impl Db {
+    fn new() -> Self {
+        Self {
+            session_id: Id(rand::random()),
+            connections: 0,
+            channels: HashMap::new(),
+            host_cache: HostCache::new(HOST_CACHE_CAPACITY, HOST_CACHE_TTL),
+            router: BcstRouter::default(),
+        }
+    }
+}
 
 const AGENT_NAME: &str = concat!("PeerCastOxide/", env!("CARGO_PKG_VERSION"));
+const PCP_VERSION: u32 = 1218;
+/// Where the viewer's own servent listens, as PeerCast does by default.
+const LOCAL_SERVENT: &str = "127.0.0.1:7144";
+/// PeerCast keeps up to 100 hosts.
+const HOST_CACHE_CAPACITY: usize = 100;
+/// Relays report themselves every minute or so.
+const HOST_CACHE_TTL: Duration = Duration::from_secs(180);
 
 async fn process_pcp(
     stream: TcpStream,
     _server_start_time: Instant,
     db: Arc<RwLock<Db>>,
 ) -> Result<()> {
-    struct ScopeExit(Arc<RwLock<Db>>);
+    struct ScopeExit(Arc<RwLock<Db>>, Option<SessionKey>);
     impl Drop for ScopeExit {
         fn drop(&mut self) {
-            self.0.write().unwrap().0 -= 1;
+            let mut db = self.0.write().unwrap();
+            db.connections -= 1;
+            if let Some(key) = self.1 {
+                db.router.unregister(key);
+            }
         }
     }
-    let _scope = ScopeExit(db.clone());
-    db.write().unwrap().0 += 1;
+    let mut scope = ScopeExit(db.clone(), None);
+    db.write().unwrap().connections += 1;
 
-    let session_id = Id(rand::random());
+    let session_id = db.read().unwrap().session_id.clone();
     let peer_addr = stream.peer_addr()?;
     let (reader, writer) = stream.into_split();
     let mut reader = AtomStreamReader::new(reader);
     let mut writer = AtomStreamWriter::new(writer);
 
-    timeout(
+    let handshake = timeout(
         Duration::from_secs(15),
         handshake(
             &mut reader,
@@ -61,27 +100,75 @@ async fn process_pcp(
             &session_id,
             peer_addr.ip(),
             AGENT_NAME,
+            PCP_VERSION,
             Duration::from_secs(5),
         ),
     )
     .await??;
+    let helo = &handshake.helo;
+    // Verified by the ping, so it fills in the family missing from the peer's own `host` reports
+    let peer_addr = SocketAddr::new(handshake.peer_ip, handshake.peer_port);
+
+    let (session_key, mut receiver) = db.write().unwrap().router.register(helo.sid.clone());
+    scope.1 = Some(session_key);
+    spawn(async move {
+        while let Some(atom) = receiver.recv().await {
+            if let Err(err) = writer.write_unknown_atom(&atom).await {
+                error!("{:?}", err);
+                return;
+            }
+        }
+    });
 
     loop {
         let atom = reader.read_unknown_atom().await?;
         match atom.identifier().0.as_ref() {
             BCST => {
-                let bcst: Bcst = from_unknown(atom)?;
+                let mut bcst: Bcst = match from_unknown(atom.clone()) {
+                    Ok(bcst) => bcst,
+                    Err(err) => {
+                        debug!("invalid bcst: {}: {}", err, atom);
+                        continue;
+                    }
+                };
                 let mut db = db.write().unwrap();
                 tracing::trace!("{:?}", bcst);
-                if let Some(record) = db.1.get_mut(&bcst.chan.id) {
-                    record.bcst = bcst;
+                if bcst.from == helo.sid {
+                    if let Some(host) = &mut bcst.host {
+                        host.add_verified_addr(peer_addr);
+                    }
+                    let groups = match &bcst.host {
+                        Some(host) if host.flg1.tracker() => BcstGroup::TRACKERS,
+                        _ => BcstGroup::RELAYS,
+                    };
+                    db.router.join_groups(session_key, groups);
+                }
+                let delivered = db.router.route(session_key, &session_id, &bcst, &atom);
+                if let (Some(push), Some(dest)) = (&bcst.push, &bcst.dest) {
+                    if delivered == 0 {
+                        debug!("push target not connected: {} ({:?})", dest, push);
+                    }
+                    continue;
+                }
+                if let Some(host) = &bcst.host {
+                    db.host_cache.update(host.clone(), bcst.hops);
+                }
+                let (Some(chan), Some(host)) = (bcst.chan, bcst.host) else {
+                    continue;
+                };
+                if let Some(record) = db.channels.get_mut(&chan.id) {
+                    record.chan = chan;
+                    record.host = host;
+                    record.hops = bcst.hops;
                     record.updated_at = Instant::now();
                     continue;
                 }
-                db.1.insert(
-                    bcst.chan.id.clone(),
+                db.channels.insert(
+                    chan.id.clone(),
                     Record {
-                        bcst,
+                        chan,
+                        host,
+                        hops: bcst.hops,
                         created_at: Instant::now(),
                         updated_at: Instant::now(),
                     },
@@ -98,6 +185,39 @@ async fn process_pcp(
     }
 }
 
+fn not_found() -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header(CONTENT_TYPE, "text/plain")
+        .body(Full::new(Bytes::from(
+            StatusCode::NOT_FOUND.canonical_reason().unwrap_or_default(),
+        )))
+        .unwrap()
+}
+
+/// Playlist for `/pls/<id>[.<ext>]` which lets the viewer's local servent fetch the channel from
+/// `tip`, or from the tracker when no tip is given.
+fn create_playlist_for(db: &Db, req: &ChannelRequest) -> Option<(PlaylistFormat, String)> {
+    let record = db.channels.get(&req.channel_id)?;
+    let info = &record.chan.info;
+    let format = req
+        .ext
+        .as_deref()
+        .and_then(PlaylistFormat::from_extension)
+        .unwrap_or_else(|| PlaylistFormat::for_stream_type(info.r#type.as_deref()));
+    let tip = req
+        .tip
+        .clone()
+        .or_else(|| Some(record.host.global()?.to_string()));
+    let url = stream_url(
+        LOCAL_SERVENT,
+        &record.chan.id,
+        &stream_extension(info),
+        tip.as_deref(),
+    );
+    Some((format, create_playlist(format, &info.name, &url)))
+}
+
 async fn process_http(
     stream: TcpStream,
     server_start_time: Instant,
@@ -116,18 +236,24 @@ async fn process_http(
                     )))
                     .unwrap());
             }
+            let target = req.uri().path_and_query().map(|x| x.as_str());
+            let channel_req = target.and_then(ChannelRequest::from_target);
+            if let Some(channel_req) = channel_req.filter(|x| x.kind == ChannelRequestKind::Pls) {
+                let playlist = create_playlist_for(&db.read().unwrap(), &channel_req);
+                let Some((format, body)) = playlist else {
+                    return Ok(not_found());
+                };
+                return Response::builder()
+                    .header(CONTENT_TYPE, format.mime_type())
+                    .body(Full::new(Bytes::from(body)));
+            }
             if req.uri() != "/admin?cmd=viewxml" {
-                return Ok(Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .header(CONTENT_TYPE, "text/plain")
-                    .body(Full::new(Bytes::from(
-                        StatusCode::NOT_FOUND.canonical_reason().unwrap_or_default(),
-                    )))
-                    .unwrap());
+                return Ok(not_found());
             }
             let xml = {
                 let db = db.read().unwrap();
-                create_xml(db.0, server_start_time, &db.1.values().collect::<Vec<_>>())
+                let records = db.channels.values().collect::<Vec<_>>();
+                create_xml(db.connections, server_start_time, &records, &db.host_cache)
             };
             Response::builder()
                 .header(CONTENT_TYPE, "application/xml")
@@ -140,7 +266,7 @@ async fn process_http(
 }
 
 async fn accept_connenctions_loop<Fut>(
-    addr: impl ToSocketAddrs,
+    listener: TcpListener,
     server_start_time: Instant,
     db: Arc<RwLock<Db>>,
     process: impl 'static + Clone + Send + Fn(TcpStream, Instant, Arc<RwLock<Db>>) -> Fut,
@@ -148,10 +274,9 @@ async fn accept_connenctions_loop<Fut>(
 where
     Fut: Send + Future<Output = Result<()>>,
 {
-    let listener = TcpListener::bind(&addr).await?;
     loop {
-        let (socket, _) = listener.accept().await?;
-        tracing::trace!("accept: {}", socket.peer_addr()?);
+        let (socket, addr) = listener.accept().await?;
+        tracing::trace!("accept: {}", canonical(addr));
         spawn({
             let process = process.clone();
             let db = db.clone();
@@ -164,20 +289,59 @@ where
     }
 }
 
+async fn serve(http_listener: TcpListener, pcp_listener: TcpListener) -> Result<()> {
+    let server_start_time = Instant::now();
+    let db = Arc::new(RwLock::new(Db::new()));
+    let http = accept_connenctions_loop(http_listener, server_start_time, db.clone(), process_http);
+    let pcp = accept_connenctions_loop(pcp_listener, server_start_time, db, process_pcp);
+    select_all([spawn(http), spawn(pcp)]).await.0??;
+    Ok(())
+}
+
 pub async fn listen(http_port: u16, pcp_port: u16) -> anyhow::Result<()> {
     tracing::trace!("listen");
-    let server_start_time = Instant::now();
-    let db: Arc<RwLock<Db>> = Default::default();
-
-    let futures = ["0.0.0.0", "::"].iter().flat_map(|ip| {
-        let ip = IpAddr::from_str(ip).unwrap();
-        let addr = SocketAddr::new(ip, http_port);
-        let http = accept_connenctions_loop(addr, server_start_time, db.clone(), process_http);
-        let addr = SocketAddr::new(ip, pcp_port);
-        let pcp = accept_connenctions_loop(addr, server_start_time, db.clone(), process_pcp);
-        [spawn(http), spawn(pcp)]
-    });
-    select_all(futures).await.0??;
+    serve(bind_dual_stack(http_port)?, bind_dual_stack(pcp_port)?).await
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    use peercastoxide_lib::{net::bind_dual_stack, pcp::http::read_response_head};
+    use tokio::{
+        io::{AsyncWriteExt, BufReader},
+        net::TcpStream,
+        spawn,
+    };
+
+    use super::serve;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_listen_on_ipv4_and_ipv6() {
+        let http_listener = bind_dual_stack(0).unwrap();
+        let http_port = http_listener.local_addr().unwrap().port();
+        let pcp_listener = bind_dual_stack(0).unwrap();
+        let pcp_port = pcp_listener.local_addr().unwrap().port();
+        let server = spawn(serve(http_listener, pcp_listener));
+
+        for ip in [Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()] {
+            let stream = TcpStream::connect(SocketAddr::new(ip, http_port))
+                .await
+                .unwrap();
+            let (reader, mut writer) = stream.into_split();
+            writer
+                .write_all(b"GET /admin?cmd=viewxml HTTP/1.0\r\n\r\n")
+                .await
+                .unwrap();
+            let (status, _) = read_response_head(&mut BufReader::new(reader))
+                .await
+                .unwrap();
+            assert_eq!(status, 200, "{}", ip);
+            TcpStream::connect(SocketAddr::new(ip, pcp_port))
+                .await
+                .unwrap();
+        }
+        assert!(!server.is_finished());
+        server.abort();
+    }
 }
@@ -28,15 +28,19 @@ fn default_subscriber_builder() -> fmt::Layer<Registry, DefaultFields, Format<Co
 type MyLayer<T> = fmt::Layer<Registry, DefaultFields, Format<Compact, T>>;
 
 fn init_tracing<T: FormatTime + Send + Sync + 'static>(
+    crate_name: &str,
     customize: fn(MyLayer<SystemTime>) -> MyLayer<T>,
 ) {
     let layer = customize(default_subscriber_builder());
-    const DIRECTIVES: &str = if cfg!(debug_assertions) {
-        concat!(env!("CARGO_CRATE_NAME"), "=trace,peercastoxide_lib=trace")
+    let level = if cfg!(debug_assertions) {
+        "trace"
     } else {
-        concat!(env!("CARGO_CRATE_NAME"), "=info,peercastoxide_lib=info")
+        "info"
     };
-    let filter = EnvFilter::new(DIRECTIVES);
+    let filter = EnvFilter::new(format!(
+        "{}={},peercastoxide_lib={}",
+        crate_name, level, level
+    ));
     let reg = tracing_subscriber::registry()
         .with(layer.with_filter(filter))
         .with(console_subscriber::ConsoleLayer::builder().spawn());
@@ -44,7 +48,8 @@ fn init_tracing<T: FormatTime + Send + Sync + 'static>(
     panic::set_hook(Box::new(|panic| error!("{}", panic)));
 }
 
-pub fn init() {
+/// Logs the crate named `crate_name`, usually `env!("CARGO_CRATE_NAME")`, and peercastoxide-lib.
+pub fn init(crate_name: &str) {
     if cfg!(debug_assertions) {
         env::set_var("RUST_BACKTRACE", "1");
     }
@@ -53,5 +58,7 @@ pub fn init() {
             decimal_digits: NonZeroU8::new(6),
         })
         .encode();
-    init_tracing(|layer| layer.with_timer(LocalTime::new(Iso8601::<MY_CONFIG>)));
+    init_tracing(crate_name, |layer| {
+        layer.with_timer(LocalTime::new(Iso8601::<MY_CONFIG>))
+    });
 }